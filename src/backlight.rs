@@ -0,0 +1,257 @@
+//! Display backlight control from ambient light.
+
+use embedded_hal::pwm::SetDutyCycle;
+
+/// Maps ambient lux to a perceived brightness level between 0 and 1.
+///
+/// Perceived brightness of the surroundings grows roughly with the logarithm
+/// of illuminance, so both curves work on `log10(lux)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrightnessCurve<'a> {
+    /// Straight line in log-lux between two end points, flat outside them.
+    Logarithmic {
+        min_lux: f32,
+        max_lux: f32,
+        min_level: f32,
+        max_level: f32,
+    },
+    /// `(lux, level)` points in ascending lux, interpolated in log-lux and flat
+    /// outside the first and last point.
+    Piecewise(&'a [(f32, f32)]),
+}
+
+impl BrightnessCurve<'_> {
+    /// 1 lux at 5% up to 10000 lux at full brightness.
+    pub const DEFAULT: BrightnessCurve<'static> = BrightnessCurve::Logarithmic {
+        min_lux: 1.0,
+        max_lux: 10_000.0,
+        min_level: 0.05,
+        max_level: 1.0,
+    };
+
+    pub fn level(&self, lux: f32) -> f32 {
+        let level = match *self {
+            BrightnessCurve::Logarithmic {
+                min_lux,
+                max_lux,
+                min_level,
+                max_level,
+            } => interpolate(lux, (min_lux, min_level), (max_lux, max_level)),
+            BrightnessCurve::Piecewise(points) => match points {
+                [] => 1.0,
+                [(_, level)] => *level,
+                _ => {
+                    let upper = points
+                        .iter()
+                        .position(|&(point_lux, _)| point_lux >= lux)
+                        .unwrap_or(points.len() - 1)
+                        .max(1);
+                    interpolate(lux, points[upper - 1], points[upper])
+                }
+            },
+        };
+        level.clamp(0.0, 1.0)
+    }
+}
+
+impl Default for BrightnessCurve<'_> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Level at `lux` on the log-lux line through `low` and `high`, flat outside.
+fn interpolate(
+    lux: f32,
+    (low_lux, low_level): (f32, f32),
+    (high_lux, high_level): (f32, f32),
+) -> f32 {
+    // Keep the logarithms finite in darkness.
+    let log = |lux: f32| libm::log10f(lux.max(1e-3));
+    let span = log(high_lux) - log(low_lux);
+    if span <= 0.0 {
+        return if lux < high_lux {
+            low_level
+        } else {
+            high_level
+        };
+    }
+    let t = ((log(lux) - log(low_lux)) / span).clamp(0.0, 1.0);
+    low_level + (high_level - low_level) * t
+}
+
+/// Turns lux readings into a steady backlight duty cycle.
+///
+/// The target level only moves once the curve's level differs from it by more
+/// than `hysteresis`, so noise around a threshold doesn't flicker the display,
+/// and the output level approaches the target by at most `max_rate` per second
+/// so it doesn't pump. The first reading sets the level directly. The level is
+/// perceptual; the duty cycle is `level^gamma` to account for the display's
+/// roughly power-law luminance response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BacklightController<'a> {
+    pub curve: BrightnessCurve<'a>,
+    pub gamma: f32,
+    /// Level change needed to move the target
+    pub hysteresis: f32,
+    /// Largest level change per second; the level holds if not positive
+    pub max_rate: f32,
+    target: Option<f32>,
+    level: f32,
+}
+
+impl<'a> BacklightController<'a> {
+    pub fn new(curve: BrightnessCurve<'a>) -> Self {
+        BacklightController {
+            curve,
+            gamma: 2.2,
+            hysteresis: 0.05,
+            max_rate: 0.5,
+            target: None,
+            level: 1.0,
+        }
+    }
+
+    /// Feed a lux reading taken `elapsed_ms` after the previous one and return
+    /// the new level. Non-finite readings are ignored.
+    pub fn update(&mut self, lux: f32, elapsed_ms: u32) -> f32 {
+        if !lux.is_finite() {
+            return self.level;
+        }
+        let wanted = self.curve.level(lux);
+        match self.target {
+            None => {
+                self.target = Some(wanted);
+                self.level = wanted;
+                return wanted;
+            }
+            Some(target) if libm::fabsf(wanted - target) > self.hysteresis => {
+                self.target = Some(wanted)
+            }
+            Some(_) => {}
+        }
+
+        let target = self.target.unwrap_or(wanted);
+        // `max` also turns a NaN rate into zero, which `clamp` would panic on.
+        let step = (self.max_rate * elapsed_ms as f32 / 1000.0).max(0.0);
+        self.level += (target - self.level).clamp(-step, step);
+        self.level
+    }
+
+    /// Current perceptual level between 0 and 1.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Current level as a duty cycle out of `max_duty`.
+    pub fn duty_cycle(&self, max_duty: u16) -> u16 {
+        let duty = libm::powf(self.level, self.gamma) * max_duty as f32 + 0.5;
+        if duty >= max_duty as f32 {
+            max_duty
+        } else {
+            duty as u16
+        }
+    }
+
+    /// [`update`](Self::update) and write the resulting duty cycle to `pwm`.
+    pub fn apply<P: SetDutyCycle>(
+        &mut self,
+        pwm: &mut P,
+        lux: f32,
+        elapsed_ms: u32,
+    ) -> Result<(), P::Error> {
+        self.update(lux, elapsed_ms);
+        pwm.set_duty_cycle(self.duty_cycle(pwm.max_duty_cycle()))
+    }
+}
+
+impl Default for BacklightController<'_> {
+    fn default() -> Self {
+        Self::new(BrightnessCurve::DEFAULT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn first_reading_sets_the_level() {
+        let mut controller = BacklightController::default();
+        // Halfway up the default curve in log-lux
+        assert_close(controller.update(100.0, 0), 0.525);
+        assert_close(controller.level(), 0.525);
+    }
+
+    #[test]
+    fn changes_within_hysteresis_are_ignored() {
+        let mut controller = BacklightController::default();
+        controller.update(100.0, 0);
+        // 140 lux is 0.035 up the curve, 70 lux 0.037 down
+        for lux in [140.0, 70.0, 140.0, 70.0, f32::NAN, f32::INFINITY] {
+            assert_close(controller.update(lux, 1000), 0.525);
+        }
+        // The target, not the last reading, is what the next one is compared to.
+        for _ in 0..10 {
+            assert_close(controller.update(140.0, 1000), 0.525);
+        }
+
+        // 200 lux is 0.0715 up the curve.
+        controller.update(200.0, 1000);
+        assert_close(controller.level(), 0.5965);
+    }
+
+    #[test]
+    fn level_moves_at_most_max_rate() {
+        let mut controller = BacklightController::default();
+        controller.update(1.0, 0);
+        assert_close(controller.level(), 0.05);
+
+        assert_close(controller.update(10_000.0, 100), 0.1);
+        assert_close(controller.update(10_000.0, 1000), 0.6);
+        // Stops at the target rather than overshooting
+        assert_close(controller.update(10_000.0, 2000), 1.0);
+
+        controller.max_rate = 0.1;
+        assert_close(controller.update(1.0, 500), 0.95);
+        assert_close(controller.update(1.0, 0), 0.95);
+        assert_close(controller.update(1.0, 60_000), 0.05);
+    }
+
+    #[test]
+    fn duty_cycle_applies_gamma() {
+        let mut controller = BacklightController::default();
+        controller.update(10_000.0, 0);
+        assert_eq!(controller.duty_cycle(1000), 1000);
+        controller.update(1.0, 60_000);
+        // 0.05^2.2 of 1000
+        assert_eq!(controller.duty_cycle(1000), 1);
+        controller.update(100.0, 60_000);
+        assert_eq!(controller.duty_cycle(1000), 242);
+    }
+
+    #[test]
+    fn invalid_max_rate_holds_the_level() {
+        for max_rate in [-0.5, 0.0, f32::NAN] {
+            let mut controller = BacklightController::default();
+            controller.update(1.0, 0);
+            controller.max_rate = max_rate;
+            assert_close(controller.update(10_000.0, 1000), 0.05);
+        }
+
+        let mut controller = BacklightController::default();
+        controller.update(1.0, 0);
+        controller.max_rate = f32::INFINITY;
+        assert_close(controller.update(10_000.0, 0), 0.05);
+        assert_close(controller.update(10_000.0, 1), 1.0);
+    }
+}
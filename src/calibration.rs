@@ -0,0 +1,616 @@
+//! Per-device calibration data.
+
+use crate::{
+    lux_conversion::{check_overflow, DynLuxConverter},
+    types::{Gain, IntegrationTime},
+};
+
+/// Smallest CH0 count at the lower gain of a pair for its ratio to be trusted.
+const MIN_RATIO_COUNTS: u16 = 100;
+
+pub(crate) fn gain_index(gain: Gain) -> usize {
+    match gain {
+        Gain::Low => 0,
+        Gain::Med => 1,
+        Gain::High => 2,
+        Gain::Max => 3,
+    }
+}
+
+/// Measured gain multipliers, relative to [`Gain::Low`].
+///
+/// The nominal multipliers from [`Gain::get_multiplier`] are only accurate to
+/// a few percent per part, which shows up as steps in lux when the gain is
+/// switched. Once set with
+/// [`Tsl2591::set_gain_calibration`](crate::Tsl2591::set_gain_calibration),
+/// the driver corrects every lux result for the measured value.
+///
+/// The calibration stores the ratio of each gain to the next lower one, since
+/// a single light level can't be measured without saturation at both
+/// [`Gain::Low`] and [`Gain::Max`]. Steps that were never measured use their
+/// nominal ratio; [`steps`](Self::steps) tells which ones were.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GainCalibration {
+    steps: [Option<f32>; 3],
+}
+
+impl GainCalibration {
+    pub const NOMINAL: Self = GainCalibration { steps: [None; 3] };
+
+    /// Calibration from multipliers relative to [`Gain::Low`], all treated as
+    /// measured.
+    pub fn new(med: f32, high: f32, max: f32) -> Self {
+        GainCalibration {
+            steps: [Some(med), Some(high / med), Some(max / high)],
+        }
+    }
+
+    /// Derive step ratios from CH0 readings of the same steady light taken at
+    /// several gains with `integration_time`.
+    ///
+    /// Adjacent gains are compared; readings at the same gain are averaged. A
+    /// step whose readings are missing, saturated or too dim is left
+    /// unmeasured.
+    pub fn from_readings(integration_time: IntegrationTime, readings: &[(Gain, u16)]) -> Self {
+        let mut sums = [0u32; 4];
+        let mut counts = [0u32; 4];
+        let mut saturated = [false; 4];
+        for &(gain, ch_0) in readings {
+            let i = gain_index(gain);
+            saturated[i] |= check_overflow(integration_time, ch_0, 0);
+            sums[i] += ch_0 as u32;
+            counts[i] += 1;
+        }
+
+        let mut calibration = Self::NOMINAL;
+        for i in 1..Gain::ALL.len() {
+            let usable = counts[i - 1] > 0
+                && counts[i] > 0
+                && !saturated[i - 1]
+                && !saturated[i]
+                && sums[i - 1] >= MIN_RATIO_COUNTS as u32 * counts[i - 1];
+            if usable {
+                calibration.steps[i - 1] = Some(
+                    (sums[i] as f32 / counts[i] as f32)
+                        / (sums[i - 1] as f32 / counts[i - 1] as f32),
+                );
+            }
+        }
+        calibration
+    }
+
+    /// Measured ratio of `gain` to the next lower gain, indexed Med, High,
+    /// Max. `None` where the step was not measured.
+    pub fn steps(&self) -> [Option<f32>; 3] {
+        self.steps
+    }
+
+    /// Measured ratio of `gain` to the next lower gain; `None` for
+    /// [`Gain::Low`] or an unmeasured step.
+    pub fn step(&self, gain: Gain) -> Option<f32> {
+        match gain_index(gain) {
+            0 => None,
+            i => self.steps[i - 1],
+        }
+    }
+
+    /// Set or clear the measured ratio of `gain` to the next lower gain.
+    /// Ignored for [`Gain::Low`].
+    pub fn set_step(&mut self, gain: Gain, ratio: Option<f32>) {
+        if let Some(i) = gain_index(gain).checked_sub(1) {
+            self.steps[i] = ratio;
+        }
+    }
+
+    /// Every step has been measured.
+    pub fn is_complete(&self) -> bool {
+        self.steps.iter().all(Option::is_some)
+    }
+
+    /// Combine with a calibration taken under different light; steps measured
+    /// in `other` take precedence.
+    pub fn merge(self, other: GainCalibration) -> Self {
+        let mut merged = self;
+        for (step, other) in merged.steps.iter_mut().zip(other.steps.iter()) {
+            if other.is_some() {
+                *step = *other;
+            }
+        }
+        merged
+    }
+
+    pub fn multiplier(&self, gain: Gain) -> f32 {
+        let mut multiplier = 1.0;
+        for i in 1..=gain_index(gain) {
+            multiplier *= self.steps[i - 1].unwrap_or(
+                Gain::ALL[i].get_multiplier() as f32 / Gain::ALL[i - 1].get_multiplier() as f32,
+            );
+        }
+        multiplier
+    }
+
+    /// Factor converting lux computed with the nominal multiplier into lux
+    /// computed with the measured one.
+    pub fn correction(&self, gain: Gain) -> f32 {
+        gain.get_multiplier() as f32 / self.multiplier(gain)
+    }
+
+    pub fn correct_lux(&self, gain: Gain, lux: f32) -> f32 {
+        lux * self.correction(gain)
+    }
+
+    pub fn correct_nano_lux(&self, gain: Gain, nano_lux: i64) -> i64 {
+        (nano_lux as f64 * self.correction(gain) as f64) as i64
+    }
+}
+
+impl Default for GainCalibration {
+    fn default() -> Self {
+        Self::NOMINAL
+    }
+}
+
+/// CH0/CH1 counts read in darkness, per gain and integration time.
+///
+/// At [`Gain::Max`] this offset dominates lux readings in near-darkness.
+/// Record it with [`Tsl2591::calibrate_dark`](crate::Tsl2591::calibrate_dark)
+/// and subtract it either in the driver, with
+/// [`Tsl2591::set_dark_offsets`](crate::Tsl2591::set_dark_offsets), or around
+/// any converter with [`DarkCompensated`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DarkOffsets {
+    offsets: [[(u16, u16); 6]; 4],
+}
+
+impl DarkOffsets {
+    pub fn offset(&self, gain: Gain, integration_time: IntegrationTime) -> (u16, u16) {
+        self.offsets[gain_index(gain)][integration_time as usize]
+    }
+
+    pub fn set_offset(
+        &mut self,
+        gain: Gain,
+        integration_time: IntegrationTime,
+        (ch_0, ch_1): (u16, u16),
+    ) {
+        self.offsets[gain_index(gain)][integration_time as usize] = (ch_0, ch_1);
+    }
+
+    /// Subtract the offset from raw channel data, clamping at zero.
+    pub fn compensate(
+        &self,
+        gain: Gain,
+        integration_time: IntegrationTime,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> (u16, u16) {
+        let (dark_0, dark_1) = self.offset(gain, integration_time);
+        (ch_0.saturating_sub(dark_0), ch_1.saturating_sub(dark_1))
+    }
+}
+
+/// Wraps a converter, subtracting dark offsets from the channel data before
+/// it runs. Saturation is still detected on the raw counts.
+///
+/// Meant for converters used outside the driver. Passing one to
+/// [`Tsl2591::lux_with`](crate::Tsl2591::lux_with) while
+/// [`Tsl2591::set_dark_offsets`](crate::Tsl2591::set_dark_offsets) is in use
+/// subtracts the offsets twice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DarkCompensated<C> {
+    pub converter: C,
+    pub offsets: DarkOffsets,
+}
+
+impl<C: DynLuxConverter> DynLuxConverter for DarkCompensated<C> {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            return None;
+        }
+        let (ch_0, ch_1) = self.offsets.compensate(gain, integration_time, ch_0, ch_1);
+        self.converter.nano_lux(integration_time, gain, ch_0, ch_1)
+    }
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            return None;
+        }
+        let (ch_0, ch_1) = self.offsets.compensate(gain, integration_time, ch_0, ch_1);
+        self.converter.lux(integration_time, gain, ch_0, ch_1)
+    }
+}
+
+/// Linear model of how the channel responses drift with temperature:
+/// `response(T) = response(T_ref) * (1 + coefficient * (T - T_ref))`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureModel {
+    pub reference_celsius: f32,
+    /// Relative change of the CH0 response per degree Celsius
+    pub ch_0_coefficient: f32,
+    /// Relative change of the CH1 response per degree Celsius
+    pub ch_1_coefficient: f32,
+}
+
+impl TemperatureModel {
+    /// No compensation.
+    pub const NONE: Self = TemperatureModel {
+        reference_celsius: 25.0,
+        ch_0_coefficient: 0.0,
+        ch_1_coefficient: 0.0,
+    };
+
+    /// Scale raw channel data back to the response at the reference
+    /// temperature.
+    ///
+    /// Returns `None` if the model gives a non-positive response at
+    /// `temperature_celsius`, or if a compensated count would reach the
+    /// overflow count of `integration_time` and so can't be told apart from
+    /// saturation.
+    pub fn compensate(
+        &self,
+        temperature_celsius: f32,
+        integration_time: IntegrationTime,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<(u16, u16)> {
+        let delta = temperature_celsius - self.reference_celsius;
+        let limit = integration_time.max_count();
+        let scale = |count: u16, coefficient: f32| {
+            let factor = 1.0 + coefficient * delta;
+            if factor.is_nan() || factor <= 0.0 {
+                return None;
+            }
+            let compensated = count as f32 / factor + 0.5;
+            if compensated < limit as f32 {
+                Some(compensated as u16)
+            } else {
+                None
+            }
+        };
+        Some((
+            scale(ch_0, self.ch_0_coefficient)?,
+            scale(ch_1, self.ch_1_coefficient)?,
+        ))
+    }
+}
+
+impl Default for TemperatureModel {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Wraps a converter, correcting the channel data for the sensor temperature
+/// before it runs. Update the temperature from an external sensor with
+/// [`set_temperature`](Self::set_temperature). Readings the model can't
+/// compensate, see [`TemperatureModel::compensate`], convert to `None` like an
+/// overflow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureCompensated<C> {
+    pub converter: C,
+    pub model: TemperatureModel,
+    temperature_celsius: f32,
+}
+
+impl<C> TemperatureCompensated<C> {
+    /// Starts out at the model's reference temperature.
+    pub fn new(converter: C, model: TemperatureModel) -> Self {
+        TemperatureCompensated {
+            converter,
+            model,
+            temperature_celsius: model.reference_celsius,
+        }
+    }
+
+    pub fn set_temperature(&mut self, temperature_celsius: f32) {
+        self.temperature_celsius = temperature_celsius;
+    }
+
+    pub fn temperature(&self) -> f32 {
+        self.temperature_celsius
+    }
+}
+
+impl<C: DynLuxConverter> DynLuxConverter for TemperatureCompensated<C> {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            return None;
+        }
+        let (ch_0, ch_1) =
+            self.model
+                .compensate(self.temperature_celsius, integration_time, ch_0, ch_1)?;
+        self.converter.nano_lux(integration_time, gain, ch_0, ch_1)
+    }
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            return None;
+        }
+        let (ch_0, ch_1) =
+            self.model
+                .compensate(self.temperature_celsius, integration_time, ch_0, ch_1)?;
+        self.converter.lux(integration_time, gain, ch_0, ch_1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lux_conversion::{AmsLuxConverter, LuxCoefficients};
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected.abs() * 1e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn gain_steps_chain_into_multipliers() {
+        let calibration = GainCalibration::new(24.0, 408.0, 9384.0);
+        assert_eq!(calibration.steps(), [Some(24.0), Some(17.0), Some(23.0)]);
+        assert!(calibration.is_complete());
+        assert_close(calibration.multiplier(Gain::Low), 1.0);
+        assert_close(calibration.multiplier(Gain::Med), 24.0);
+        assert_close(calibration.multiplier(Gain::High), 408.0);
+        assert_close(calibration.multiplier(Gain::Max), 9384.0);
+
+        assert_close(calibration.correction(Gain::Low), 1.0);
+        assert_close(calibration.correction(Gain::Max), 9876.0 / 9384.0);
+        assert_close(
+            calibration.correct_lux(Gain::Med, 100.0),
+            100.0 * 25.0 / 24.0,
+        );
+        let nano_lux = calibration.correct_nano_lux(Gain::Med, 24_000_000_000);
+        assert!((nano_lux - 25_000_000_000).abs() < 10_000, "{}", nano_lux);
+    }
+
+    #[test]
+    fn unmeasured_steps_fall_back_to_nominal() {
+        for &gain in &Gain::ALL {
+            assert_close(GainCalibration::NOMINAL.correction(gain), 1.0);
+        }
+
+        let mut calibration = GainCalibration::NOMINAL;
+        calibration.set_step(Gain::Max, Some(23.0));
+        calibration.set_step(Gain::Low, Some(2.0));
+        assert!(!calibration.is_complete());
+        assert_eq!(calibration.step(Gain::Low), None);
+        assert_eq!(calibration.step(Gain::Med), None);
+        assert_close(calibration.multiplier(Gain::High), 428.0);
+        assert_close(calibration.multiplier(Gain::Max), 428.0 * 23.0);
+        assert_close(calibration.correction(Gain::High), 1.0);
+    }
+
+    #[test]
+    fn merge_prefers_measured_steps_of_other() {
+        let mut bright = GainCalibration::NOMINAL;
+        bright.set_step(Gain::Med, Some(24.0));
+        bright.set_step(Gain::High, Some(17.0));
+        let mut dim = GainCalibration::NOMINAL;
+        dim.set_step(Gain::High, Some(18.0));
+        dim.set_step(Gain::Max, Some(22.0));
+
+        assert_eq!(
+            bright.merge(dim).steps(),
+            [Some(24.0), Some(18.0), Some(22.0)]
+        );
+        assert_eq!(
+            dim.merge(bright).steps(),
+            [Some(24.0), Some(17.0), Some(22.0)]
+        );
+        assert_eq!(bright.merge(GainCalibration::NOMINAL), bright);
+    }
+
+    #[test]
+    fn readings_give_adjacent_ratios() {
+        let calibration = GainCalibration::from_readings(
+            IntegrationTime::_200MS,
+            &[
+                (Gain::Low, 990),
+                (Gain::Med, 24_400),
+                (Gain::Low, 1010),
+                (Gain::Med, 24_600),
+            ],
+        );
+        assert_eq!(calibration.steps(), [Some(24.5), None, None]);
+
+        let calibration = GainCalibration::from_readings(
+            IntegrationTime::_200MS,
+            &[(Gain::Med, 160), (Gain::High, 2720), (Gain::Max, 62_560)],
+        );
+        assert_eq!(calibration.steps(), [None, Some(17.0), Some(23.0)]);
+    }
+
+    #[test]
+    fn saturated_or_dim_pairs_stay_unmeasured() {
+        // Max saturates at 100 ms long before 65535
+        let calibration = GainCalibration::from_readings(
+            IntegrationTime::_100MS,
+            &[(Gain::High, 1600), (Gain::Max, 36_863)],
+        );
+        assert_eq!(calibration, GainCalibration::NOMINAL);
+
+        // One saturated reading spoils the average
+        let calibration = GainCalibration::from_readings(
+            IntegrationTime::_200MS,
+            &[(Gain::Low, 2000), (Gain::Med, 50_000), (Gain::Med, 65_535)],
+        );
+        assert_eq!(calibration, GainCalibration::NOMINAL);
+
+        // Too few counts at the lower gain for a trustworthy ratio
+        let calibration = GainCalibration::from_readings(
+            IntegrationTime::_200MS,
+            &[(Gain::Low, 99), (Gain::Med, 2475)],
+        );
+        assert_eq!(calibration, GainCalibration::NOMINAL);
+
+        // Both gains of the pair are needed
+        let calibration =
+            GainCalibration::from_readings(IntegrationTime::_200MS, &[(Gain::Max, 30_000)]);
+        assert_eq!(calibration, GainCalibration::NOMINAL);
+    }
+
+    #[test]
+    fn dark_offsets_clamp_at_zero() {
+        let mut offsets = DarkOffsets::default();
+        offsets.set_offset(Gain::Max, IntegrationTime::_600MS, (40, 12));
+        assert_eq!(offsets.offset(Gain::Max, IntegrationTime::_600MS), (40, 12));
+        assert_eq!(
+            offsets.compensate(Gain::Max, IntegrationTime::_600MS, 100, 30),
+            (60, 18)
+        );
+        assert_eq!(
+            offsets.compensate(Gain::Max, IntegrationTime::_600MS, 25, 12),
+            (0, 0)
+        );
+        // Other settings keep their own (here zero) offset
+        assert_eq!(
+            offsets.compensate(Gain::Max, IntegrationTime::_500MS, 25, 12),
+            (25, 12)
+        );
+        assert_eq!(
+            offsets.compensate(Gain::High, IntegrationTime::_600MS, 25, 12),
+            (25, 12)
+        );
+    }
+
+    #[test]
+    fn dark_compensated_judges_overflow_on_raw_counts() {
+        let converter = AmsLuxConverter::new(LuxCoefficients::DN40);
+        let mut offsets = DarkOffsets::default();
+        offsets.set_offset(Gain::Low, IntegrationTime::_100MS, (1000, 100));
+        let compensated = DarkCompensated { converter, offsets };
+
+        let lux = |c: &dyn DynLuxConverter, ch_0, ch_1| {
+            c.lux(IntegrationTime::_100MS, Gain::Low, ch_0, ch_1)
+        };
+        assert_eq!(lux(&compensated, 3000, 300), lux(&converter, 2000, 200));
+        assert_eq!(
+            compensated.nano_lux(IntegrationTime::_100MS, Gain::Low, 3000, 300),
+            converter.nano_lux(IntegrationTime::_100MS, Gain::Low, 2000, 200)
+        );
+        // 36863 would not overflow after subtracting the offset
+        assert_eq!(lux(&compensated, 36_863, 300), None);
+        assert_eq!(
+            compensated.nano_lux(IntegrationTime::_100MS, Gain::Low, 36_863, 300),
+            None
+        );
+        assert!(lux(&compensated, 36_862, 300).is_some());
+        assert_eq!(lux(&compensated, 500, 50), Some(0.0));
+    }
+
+    const MODEL: TemperatureModel = TemperatureModel {
+        reference_celsius: 25.0,
+        ch_0_coefficient: -0.002,
+        ch_1_coefficient: 0.004,
+    };
+
+    #[test]
+    fn temperature_compensation_over_operating_range() {
+        let (ref_0, ref_1) = (20_000.0f32, 5_000.0f32);
+        for celsius in (-20..=60).step_by(5) {
+            let delta = celsius as f32 - 25.0;
+            let raw_0 = ref_0 * (1.0 + MODEL.ch_0_coefficient * delta);
+            let raw_1 = ref_1 * (1.0 + MODEL.ch_1_coefficient * delta);
+            let (ch_0, ch_1) = MODEL
+                .compensate(
+                    celsius as f32,
+                    IntegrationTime::_200MS,
+                    (raw_0 + 0.5) as u16,
+                    (raw_1 + 0.5) as u16,
+                )
+                .unwrap();
+            assert!(
+                (ch_0 as f32 - ref_0).abs() <= 1.0,
+                "{} °C: {}",
+                celsius,
+                ch_0
+            );
+            assert!(
+                (ch_1 as f32 - ref_1).abs() <= 1.0,
+                "{} °C: {}",
+                celsius,
+                ch_1
+            );
+        }
+
+        assert_eq!(
+            MODEL.compensate(25.0, IntegrationTime::_100MS, 1234, 567),
+            Some((1234, 567))
+        );
+        assert_eq!(
+            TemperatureModel::NONE.compensate(-20.0, IntegrationTime::_100MS, 1234, 567),
+            Some((1234, 567))
+        );
+    }
+
+    #[test]
+    fn temperature_compensation_reports_unusable_results() {
+        // 1 + 0.03 * (-20 - 25) is negative
+        let steep = TemperatureModel {
+            ch_1_coefficient: 0.03,
+            ..MODEL
+        };
+        assert_eq!(
+            steep.compensate(-20.0, IntegrationTime::_200MS, 1000, 100),
+            None
+        );
+        assert_eq!(
+            MODEL.compensate(f32::NAN, IntegrationTime::_200MS, 1000, 100),
+            None
+        );
+
+        // At 60 °C CH0 reads 7% low; corrected, 34500 would pass the 100 ms
+        // overflow count.
+        assert_eq!(
+            MODEL.compensate(60.0, IntegrationTime::_100MS, 34_500, 100),
+            None
+        );
+        assert!(MODEL
+            .compensate(60.0, IntegrationTime::_200MS, 34_500, 100)
+            .is_some());
+
+        let converter =
+            TemperatureCompensated::new(AmsLuxConverter::new(LuxCoefficients::DN40), steep);
+        assert!(converter
+            .lux(IntegrationTime::_200MS, Gain::Low, 1000, 100)
+            .is_some());
+        let mut converter = converter;
+        converter.set_temperature(-20.0);
+        assert_eq!(
+            converter.lux(IntegrationTime::_200MS, Gain::Low, 1000, 100),
+            None
+        );
+        assert_eq!(
+            converter.nano_lux(IntegrationTime::_200MS, Gain::Low, 1000, 100),
+            None
+        );
+    }
+}
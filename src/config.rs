@@ -0,0 +1,298 @@
+use crate::types::{Enable, Gain, IntegrationTime, Persistence};
+
+/// A complete, validated sensor configuration.
+///
+/// Build one with [`Config::builder`] or start from one of the presets, and
+/// write it with [`Tsl2591::apply_config`](crate::Tsl2591::apply_config).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub(crate) gain: Gain,
+    pub(crate) integration_time: IntegrationTime,
+    pub(crate) enable: Enable,
+    pub(crate) thresholds: (u16, u16),
+    pub(crate) no_persist_thresholds: (u16, u16),
+    pub(crate) persistence: Persistence,
+}
+
+/// Reasons a [`ConfigBuilder`] can be rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A low threshold is above its high threshold
+    ThresholdOrder,
+    /// A threshold is above the largest count the ADC can reach at the chosen
+    /// integration time, so it can never be crossed
+    ThresholdOutOfRange,
+    /// An interrupt is enabled while the ALS is disabled
+    InterruptWithoutAls,
+    /// Sleep-after-interrupt is enabled without any interrupt
+    SleepWithoutInterrupt,
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Medium gain and 200 ms integration, for typical indoor lighting.
+    pub fn indoor() -> Self {
+        Self::preset(Gain::Med, IntegrationTime::_200MS)
+    }
+
+    /// Low gain and 100 ms integration, for daylight without saturating.
+    pub fn outdoor() -> Self {
+        Self::preset(Gain::Low, IntegrationTime::_100MS)
+    }
+
+    /// Maximum gain and 600 ms integration, for dim scenes.
+    pub fn low_light() -> Self {
+        Self::preset(Gain::Max, IntegrationTime::_600MS)
+    }
+
+    fn preset(gain: Gain, integration_time: IntegrationTime) -> Self {
+        let mut enable = Enable::default();
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        Config {
+            gain,
+            integration_time,
+            enable,
+            thresholds: (0, 0),
+            no_persist_thresholds: (0, 0),
+            persistence: Persistence::Every,
+        }
+    }
+
+    /// Continue building from this configuration.
+    pub fn to_builder(self) -> ConfigBuilder {
+        ConfigBuilder { config: self }
+    }
+
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    pub fn integration_time(&self) -> IntegrationTime {
+        self.integration_time
+    }
+
+    pub fn enable(&self) -> Enable {
+        self.enable
+    }
+
+    pub fn thresholds(&self) -> (u16, u16) {
+        self.thresholds
+    }
+
+    pub fn no_persist_thresholds(&self) -> (u16, u16) {
+        self.no_persist_thresholds
+    }
+
+    pub fn persistence(&self) -> Persistence {
+        self.persistence
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let max_count = self.integration_time.max_count();
+        for &(low, high) in &[self.thresholds, self.no_persist_thresholds] {
+            if low > high {
+                return Err(ConfigError::ThresholdOrder);
+            }
+            if high > max_count {
+                return Err(ConfigError::ThresholdOutOfRange);
+            }
+        }
+
+        let interrupts = self.enable.AIEN() || self.enable.NPIEN();
+        if interrupts && !self.enable.AEN() {
+            return Err(ConfigError::InterruptWithoutAls);
+        }
+        if self.enable.SAI() && !interrupts {
+            return Err(ConfigError::SleepWithoutInterrupt);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::preset(Gain::Low, IntegrationTime::_200MS)
+    }
+}
+
+/// Builder for [`Config`]. Defaults to low gain, 200 ms integration, the ALS
+/// enabled and no interrupts.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn gain(mut self, gain: Gain) -> Self {
+        self.config.gain = gain;
+        self
+    }
+
+    pub fn integration_time(mut self, integration_time: IntegrationTime) -> Self {
+        self.config.integration_time = integration_time;
+        self
+    }
+
+    /// Power on and enable the ALS (PON and AEN).
+    pub fn als(mut self, enabled: bool) -> Self {
+        self.config.enable.set_PON(enabled);
+        self.config.enable.set_AEN(enabled);
+        self
+    }
+
+    /// Enable the persistent ALS interrupt (AIEN).
+    pub fn als_interrupt(mut self, enabled: bool) -> Self {
+        self.config.enable.set_AIEN(enabled);
+        self
+    }
+
+    /// Enable the no-persist ALS interrupt (NPIEN).
+    pub fn no_persist_interrupt(mut self, enabled: bool) -> Self {
+        self.config.enable.set_NPIEN(enabled);
+        self
+    }
+
+    /// Power down after an interrupt is asserted (SAI).
+    pub fn sleep_after_interrupt(mut self, enabled: bool) -> Self {
+        self.config.enable.set_SAI(enabled);
+        self
+    }
+
+    pub fn thresholds(mut self, low: u16, high: u16) -> Self {
+        self.config.thresholds = (low, high);
+        self
+    }
+
+    pub fn no_persist_thresholds(mut self, low: u16, high: u16) -> Self {
+        self.config.no_persist_thresholds = (low, high);
+        self
+    }
+
+    pub fn persistence(mut self, persistence: Persistence) -> Self {
+        self.config.persistence = persistence;
+        self
+    }
+
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for config in [
+            Config::default(),
+            Config::indoor(),
+            Config::outdoor(),
+            Config::low_light(),
+        ] {
+            assert_eq!(config.validate(), Ok(()));
+            assert!(config.enable().PON() && config.enable().AEN());
+            assert!(!config.enable().AIEN() && !config.enable().NPIEN());
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_thresholds() {
+        assert_eq!(
+            Config::builder().thresholds(200, 100).build(),
+            Err(ConfigError::ThresholdOrder)
+        );
+        assert_eq!(
+            Config::builder().no_persist_thresholds(1, 0).build(),
+            Err(ConfigError::ThresholdOrder)
+        );
+        assert!(Config::builder().thresholds(100, 100).build().is_ok());
+    }
+
+    #[test]
+    fn thresholds_are_limited_to_the_adc_range() {
+        let short = Config::builder().integration_time(IntegrationTime::_100MS);
+        assert!(short.thresholds(0, 36863).build().is_ok());
+        assert_eq!(
+            short.thresholds(0, 36864).build(),
+            Err(ConfigError::ThresholdOutOfRange)
+        );
+        assert_eq!(
+            short.no_persist_thresholds(0, 40000).build(),
+            Err(ConfigError::ThresholdOutOfRange)
+        );
+
+        let long = Config::builder().integration_time(IntegrationTime::_200MS);
+        assert!(long.thresholds(0, 65535).build().is_ok());
+        // Checked against the integration time, whichever order it is set in
+        assert_eq!(
+            long.thresholds(0, 40000)
+                .integration_time(IntegrationTime::_100MS)
+                .build(),
+            Err(ConfigError::ThresholdOutOfRange)
+        );
+    }
+
+    #[test]
+    fn interrupts_need_the_als() {
+        let off = Config::builder().als(false);
+        assert!(off.build().is_ok());
+        assert_eq!(
+            off.als_interrupt(true).build(),
+            Err(ConfigError::InterruptWithoutAls)
+        );
+        assert_eq!(
+            off.no_persist_interrupt(true).build(),
+            Err(ConfigError::InterruptWithoutAls)
+        );
+
+        let config = Config::builder()
+            .als_interrupt(true)
+            .no_persist_interrupt(true)
+            .build()
+            .unwrap();
+        assert_eq!(config.enable().0, 0x93);
+    }
+
+    #[test]
+    fn sleep_after_interrupt_needs_an_interrupt() {
+        assert_eq!(
+            Config::builder().sleep_after_interrupt(true).build(),
+            Err(ConfigError::SleepWithoutInterrupt)
+        );
+        for builder in [
+            Config::builder().als_interrupt(true),
+            Config::builder().no_persist_interrupt(true),
+        ] {
+            let config = builder.sleep_after_interrupt(true).build().unwrap();
+            assert!(config.enable().SAI());
+        }
+    }
+
+    #[test]
+    fn to_builder_keeps_settings() {
+        let config = Config::builder()
+            .gain(Gain::High)
+            .thresholds(10, 20)
+            .no_persist_thresholds(5, 30)
+            .persistence(Persistence::_10)
+            .als_interrupt(true)
+            .build()
+            .unwrap();
+        assert_eq!(config.to_builder().build(), Ok(config));
+        assert_eq!(
+            config
+                .to_builder()
+                .gain(Gain::Low)
+                .build()
+                .unwrap()
+                .thresholds(),
+            (10, 20)
+        );
+    }
+}
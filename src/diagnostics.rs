@@ -0,0 +1,244 @@
+use crate::{
+    chip,
+    types::{Control, Enable, Persistence, Status},
+};
+use core::{convert::TryFrom, fmt};
+
+/// Outcome of [`Tsl2591::self_test`](crate::Tsl2591::self_test).
+///
+/// Each check is reported separately so a failing unit can be traced back to
+/// the part of the sensor (or its wiring) that misbehaved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// Value read from the ID register
+    pub id: u8,
+    /// The ID register matched the ID accepted when the driver was created
+    pub id_ok: bool,
+    /// A test pattern written to the ALS threshold registers read back intact
+    pub threshold_readback_ok: bool,
+    /// The force-interrupt special function raised AINT
+    pub forced_interrupt_ok: bool,
+    /// AVALID asserted within one integration cycle
+    pub valid_data_ok: bool,
+    /// Channel data sampled after AVALID, if it asserted
+    pub channels: Option<(u16, u16)>,
+    /// The channel data was plausible (no overflow and CH1 <= CH0)
+    pub channel_data_ok: bool,
+}
+
+impl SelfTestReport {
+    /// `true` if every check passed.
+    pub fn passed(&self) -> bool {
+        self.id_ok
+            && self.threshold_readback_ok
+            && self.forced_interrupt_ok
+            && self.valid_data_ok
+            && self.channel_data_ok
+    }
+}
+
+/// Every register from ENABLE (0x00) to C1DATAH (0x17), captured in one burst
+/// read by [`Tsl2591::get_register_snapshot`](crate::Tsl2591::get_register_snapshot).
+///
+/// `Display` renders a decoded, human-readable dump (including the raw bytes)
+/// suitable for pasting into a bug report.
+#[derive(Clone, Copy, Debug)]
+pub struct RegisterSnapshot {
+    pub raw: [u8; chip::REGISTER_COUNT],
+    pub enable: Enable,
+    pub control: Control,
+    pub thresholds: (u16, u16),
+    pub no_persist_thresholds: (u16, u16),
+    pub persistence: Persistence,
+    pub package_id: u8,
+    pub id: u8,
+    pub status: Status,
+    pub channels: (u16, u16),
+}
+
+impl RegisterSnapshot {
+    /// Decode a raw dump of registers 0x00 to 0x17.
+    pub fn from_raw(raw: [u8; chip::REGISTER_COUNT]) -> Self {
+        let word = |register: u8| {
+            let i = register as usize;
+            u16::from_le_bytes([raw[i], raw[i + 1]])
+        };
+        RegisterSnapshot {
+            raw,
+            enable: Enable(raw[chip::ENABLE as usize]),
+            control: Control(raw[chip::CONTROL as usize]),
+            thresholds: (
+                word(chip::TSL2591_THRESHOLD_AILTL),
+                word(chip::TSL2591_THRESHOLD_AIHTL),
+            ),
+            no_persist_thresholds: (
+                word(chip::TSL2591_THRESHOLD_NPAILTL),
+                word(chip::TSL2591_THRESHOLD_NPAIHTL),
+            ),
+            persistence: Persistence::try_from(raw[chip::TSL2591_PERSIST_FILTER as usize] & 0x0F)
+                .unwrap_or(Persistence::Every),
+            package_id: raw[chip::PID_ADDR as usize],
+            id: raw[chip::ID_ADDR as usize],
+            status: Status(raw[chip::STATUS as usize]),
+            channels: (word(chip::CHAN0_LOW), word(chip::CHAN1_LOW)),
+        }
+    }
+}
+
+fn write_flags(f: &mut fmt::Formatter<'_>, flags: &[(bool, &str)]) -> fmt::Result {
+    let mut first = true;
+    for &(set, name) in flags {
+        if set {
+            write!(f, "{}{}", if first { "" } else { " " }, name)?;
+            first = false;
+        }
+    }
+    if first {
+        write!(f, "-")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "TSL2591 register snapshot")?;
+
+        let enable = self.enable;
+        write!(f, "  ENABLE   0x{:02X} (", enable.0)?;
+        write_flags(
+            f,
+            &[
+                (enable.PON(), "PON"),
+                (enable.AEN(), "AEN"),
+                (enable.AIEN(), "AIEN"),
+                (enable.SAI(), "SAI"),
+                (enable.NPIEN(), "NPIEN"),
+            ],
+        )?;
+        writeln!(f, ")")?;
+
+        write!(
+            f,
+            "  CONTROL  0x{:02X} (gain {:?} ({}x), ",
+            self.control.0,
+            self.control.gain(),
+            self.control.gain().get_multiplier()
+        )?;
+        match self.control.integration_time() {
+            Some(time) => writeln!(f, "{} ms)", time.get_integration_time_millis())?,
+            None => writeln!(f, "reserved ATIME)")?,
+        }
+
+        writeln!(
+            f,
+            "  AILT/AIHT     {} / {}",
+            self.thresholds.0, self.thresholds.1
+        )?;
+        writeln!(
+            f,
+            "  NPAILT/NPAIHT {} / {}",
+            self.no_persist_thresholds.0, self.no_persist_thresholds.1
+        )?;
+        writeln!(
+            f,
+            "  PERSIST  0x{:02X} ({:?})",
+            self.raw[chip::TSL2591_PERSIST_FILTER as usize],
+            self.persistence
+        )?;
+        writeln!(f, "  PID      0x{:02X}", self.package_id)?;
+        writeln!(
+            f,
+            "  ID       0x{:02X}{}",
+            self.id,
+            if self.id == chip::ID {
+                ""
+            } else {
+                " (unexpected)"
+            }
+        )?;
+
+        let status = self.status;
+        write!(f, "  STATUS   0x{:02X} (", status.0)?;
+        write_flags(
+            f,
+            &[
+                (status.AVALID(), "AVALID"),
+                (status.AINT(), "AINT"),
+                (status.NPINTR(), "NPINTR"),
+            ],
+        )?;
+        writeln!(f, ")")?;
+
+        writeln!(f, "  C0DATA   {}", self.channels.0)?;
+        writeln!(f, "  C1DATA   {}", self.channels.1)?;
+
+        write!(f, "  raw     ")?;
+        for byte in self.raw.iter() {
+            write!(f, " {:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::types::{Gain, IntegrationTime};
+    use std::string::ToString;
+
+    const DUMP: [u8; chip::REGISTER_COUNT] = [
+        0x93, 0x15, 0x00, 0x00, // ENABLE, CONTROL, reserved
+        0x34, 0x12, 0xCD, 0xAB, // AILT, AIHT
+        0x02, 0x01, 0xEE, 0xFF, // NPAILT, NPAIHT
+        0xF4, 0x00, 0x00, 0x00, // PERSIST with the reserved bits set, reserved
+        0x00, 0x00, 0x50, 0x31, // reserved, PID, ID, STATUS
+        0x01, 0x02, 0x03, 0x04, // C0DATA, C1DATA
+    ];
+
+    #[test]
+    fn decodes_register_dump() {
+        let snapshot = RegisterSnapshot::from_raw(DUMP);
+        assert_eq!(snapshot.enable.0, 0x93);
+        assert_eq!(snapshot.control.gain(), Gain::Med);
+        assert_eq!(
+            snapshot.control.integration_time(),
+            Some(IntegrationTime::_600MS)
+        );
+        assert_eq!(snapshot.thresholds, (0x1234, 0xABCD));
+        assert_eq!(snapshot.no_persist_thresholds, (0x0102, 0xFFEE));
+        assert_eq!(snapshot.persistence, Persistence::_5);
+        assert_eq!(snapshot.package_id, 0x00);
+        assert_eq!(snapshot.id, chip::ID);
+        assert_eq!(snapshot.status.0, 0x31);
+        assert_eq!(snapshot.channels, (0x0201, 0x0403));
+    }
+
+    #[test]
+    fn displays_decoded_dump() {
+        let dump = RegisterSnapshot::from_raw(DUMP).to_string();
+        for line in [
+            "  ENABLE   0x93 (PON AEN AIEN NPIEN)",
+            "  CONTROL  0x15 (gain Med (25x), 600 ms)",
+            "  AILT/AIHT     4660 / 43981",
+            "  NPAILT/NPAIHT 258 / 65518",
+            "  PERSIST  0xF4 (_5)",
+            "  ID       0x50",
+            "  STATUS   0x31 (AVALID AINT NPINTR)",
+            "  C0DATA   513",
+            "  C1DATA   1027",
+            "  raw      93 15 00 00 34 12 CD AB",
+        ] {
+            assert!(dump.lines().any(|l| l.starts_with(line)), "{}", line);
+        }
+
+        let mut raw = [0u8; chip::REGISTER_COUNT];
+        raw[chip::CONTROL as usize] = 0x07;
+        raw[chip::ID_ADDR as usize] = 0x51;
+        let dump = RegisterSnapshot::from_raw(raw).to_string();
+        assert!(dump.contains("  ENABLE   0x00 (-)"));
+        assert!(dump.contains("reserved ATIME)"));
+        assert!(dump.contains("  ID       0x51 (unexpected)"));
+    }
+}
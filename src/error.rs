@@ -1,9 +1,12 @@
+use crate::types::DeviceInfo;
+
 #[derive(Clone, Copy, Debug)]
 pub enum Error<I> {
     I2c(I),
-    IdMismatch(u8),
+    IdMismatch(DeviceInfo),
     SignalOverflow,
     InfraredOverflow,
+    Timeout,
 }
 
 impl<I> From<I> for Error<I> {
@@ -0,0 +1,335 @@
+//! Incident-light exposure metering for photography.
+//!
+//! Exposure value at ISO 100 follows from illuminance as
+//! `EV100 = log2(E * 100 / C)`, where `C` is the incident-light meter
+//! calibration constant. The exposure equation `N² / t = 2^EV100 * S / 100`
+//! then relates aperture `N`, shutter time `t` in seconds and ISO speed `S`.
+//! Any lux value can be used, whichever converter produced it.
+
+/// Incident-light meter calibration constant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExposureMeter {
+    /// `C` in lux·s; ISO 2720 allows 240 to 400 for flat receptors, 250 is
+    /// common
+    pub calibration_constant: f32,
+}
+
+impl ExposureMeter {
+    pub const DEFAULT: Self = ExposureMeter {
+        calibration_constant: 250.0,
+    };
+
+    /// Exposure value at ISO 100, `None` for no light.
+    pub fn ev100(&self, lux: f32) -> Option<f32> {
+        if lux > 0.0 {
+            Some(libm::log2f(lux * 100.0 / self.calibration_constant))
+        } else {
+            None
+        }
+    }
+
+    /// Illuminance giving `ev100`, the inverse of [`ev100`](Self::ev100).
+    pub fn lux(&self, ev100: f32) -> f32 {
+        libm::exp2f(ev100) * self.calibration_constant / 100.0
+    }
+}
+
+impl Default for ExposureMeter {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Shutter time in seconds for `ev100` at `aperture` and `iso`.
+pub fn shutter(ev100: f32, aperture: f32, iso: f32) -> f32 {
+    aperture * aperture / (libm::exp2f(ev100) * iso / 100.0)
+}
+
+/// Aperture f-number for `ev100` at `shutter` seconds and `iso`.
+pub fn aperture(ev100: f32, shutter: f32, iso: f32) -> f32 {
+    libm::sqrtf(shutter * libm::exp2f(ev100) * iso / 100.0)
+}
+
+/// ISO speed for `ev100` at `aperture` and `shutter` seconds.
+pub fn iso(ev100: f32, aperture: f32, shutter: f32) -> f32 {
+    100.0 * aperture * aperture / (shutter * libm::exp2f(ev100))
+}
+
+/// Increments of the marked values on camera dials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopScale {
+    Full,
+    Half,
+    Third,
+}
+
+impl StopScale {
+    /// Marked f-numbers from f/1 to f/64.
+    pub fn apertures(self) -> &'static [f32] {
+        match self {
+            StopScale::Full => &APERTURES_FULL,
+            StopScale::Half => &APERTURES_HALF,
+            StopScale::Third => &APERTURES_THIRD,
+        }
+    }
+
+    /// Marked shutter times in seconds from 1/8000 s to 30 s.
+    pub fn shutter_speeds(self) -> &'static [f32] {
+        match self {
+            StopScale::Full => &SHUTTER_FULL,
+            StopScale::Half => &SHUTTER_HALF,
+            StopScale::Third => &SHUTTER_THIRD,
+        }
+    }
+
+    /// Marked ISO speeds from 25 to 25600.
+    pub fn iso_speeds(self) -> &'static [f32] {
+        match self {
+            StopScale::Full => &ISO_FULL,
+            StopScale::Half => &ISO_HALF,
+            StopScale::Third => &ISO_THIRD,
+        }
+    }
+
+    /// Nearest marked f-number, clamped to the ends of the scale.
+    pub fn snap_aperture(self, aperture: f32) -> f32 {
+        // Apertures are spaced by the square root of two per stop.
+        snap(self.apertures(), aperture, 2.0)
+    }
+
+    /// Nearest marked shutter time, clamped to the ends of the scale.
+    pub fn snap_shutter(self, shutter: f32) -> f32 {
+        snap(self.shutter_speeds(), shutter, 1.0)
+    }
+
+    /// Nearest marked ISO speed, clamped to the ends of the scale.
+    pub fn snap_iso(self, iso: f32) -> f32 {
+        snap(self.iso_speeds(), iso, 1.0)
+    }
+}
+
+/// Nearest value of an ascending `scale` in stops, where one stop is a factor
+/// of two in `value^exponent`.
+fn snap(scale: &[f32], value: f32, exponent: f32) -> f32 {
+    if value.is_nan() || value <= scale[0] {
+        return scale[0];
+    }
+    let last = scale[scale.len() - 1];
+    if value >= last {
+        return last;
+    }
+    let stops = |v: f32| exponent * libm::log2f(v);
+    let target = stops(value);
+    let mut best = scale[0];
+    for &marked in scale {
+        if libm::fabsf(stops(marked) - target) < libm::fabsf(stops(best) - target) {
+            best = marked;
+        }
+    }
+    best
+}
+
+const APERTURES_FULL: [f32; 13] = [
+    1.0, 1.4, 2.0, 2.8, 4.0, 5.6, 8.0, 11.0, 16.0, 22.0, 32.0, 45.0, 64.0,
+];
+
+const APERTURES_HALF: [f32; 25] = [
+    1.0, 1.2, 1.4, 1.7, 2.0, 2.4, 2.8, 3.3, 4.0, 4.8, 5.6, 6.7, 8.0, 9.5, 11.0, 13.0, 16.0, 19.0,
+    22.0, 27.0, 32.0, 38.0, 45.0, 54.0, 64.0,
+];
+
+const APERTURES_THIRD: [f32; 37] = [
+    1.0, 1.1, 1.2, 1.4, 1.6, 1.8, 2.0, 2.2, 2.5, 2.8, 3.2, 3.5, 4.0, 4.5, 5.0, 5.6, 6.3, 7.1, 8.0,
+    9.0, 10.0, 11.0, 13.0, 14.0, 16.0, 18.0, 20.0, 22.0, 25.0, 29.0, 32.0, 36.0, 40.0, 45.0, 51.0,
+    57.0, 64.0,
+];
+
+const SHUTTER_FULL: [f32; 19] = [
+    1.0 / 8000.0,
+    1.0 / 4000.0,
+    1.0 / 2000.0,
+    1.0 / 1000.0,
+    1.0 / 500.0,
+    1.0 / 250.0,
+    1.0 / 125.0,
+    1.0 / 60.0,
+    1.0 / 30.0,
+    1.0 / 15.0,
+    1.0 / 8.0,
+    1.0 / 4.0,
+    1.0 / 2.0,
+    1.0,
+    2.0,
+    4.0,
+    8.0,
+    15.0,
+    30.0,
+];
+
+const SHUTTER_HALF: [f32; 37] = [
+    1.0 / 8000.0,
+    1.0 / 6000.0,
+    1.0 / 4000.0,
+    1.0 / 3000.0,
+    1.0 / 2000.0,
+    1.0 / 1500.0,
+    1.0 / 1000.0,
+    1.0 / 750.0,
+    1.0 / 500.0,
+    1.0 / 350.0,
+    1.0 / 250.0,
+    1.0 / 180.0,
+    1.0 / 125.0,
+    1.0 / 90.0,
+    1.0 / 60.0,
+    1.0 / 45.0,
+    1.0 / 30.0,
+    1.0 / 20.0,
+    1.0 / 15.0,
+    1.0 / 10.0,
+    1.0 / 8.0,
+    1.0 / 6.0,
+    1.0 / 4.0,
+    0.3,
+    0.5,
+    0.7,
+    1.0,
+    1.5,
+    2.0,
+    3.0,
+    4.0,
+    6.0,
+    8.0,
+    12.0,
+    15.0,
+    20.0,
+    30.0,
+];
+
+const SHUTTER_THIRD: [f32; 55] = [
+    1.0 / 8000.0,
+    1.0 / 6400.0,
+    1.0 / 5000.0,
+    1.0 / 4000.0,
+    1.0 / 3200.0,
+    1.0 / 2500.0,
+    1.0 / 2000.0,
+    1.0 / 1600.0,
+    1.0 / 1250.0,
+    1.0 / 1000.0,
+    1.0 / 800.0,
+    1.0 / 640.0,
+    1.0 / 500.0,
+    1.0 / 400.0,
+    1.0 / 320.0,
+    1.0 / 250.0,
+    1.0 / 200.0,
+    1.0 / 160.0,
+    1.0 / 125.0,
+    1.0 / 100.0,
+    1.0 / 80.0,
+    1.0 / 60.0,
+    1.0 / 50.0,
+    1.0 / 40.0,
+    1.0 / 30.0,
+    1.0 / 25.0,
+    1.0 / 20.0,
+    1.0 / 15.0,
+    1.0 / 13.0,
+    1.0 / 10.0,
+    1.0 / 8.0,
+    1.0 / 6.0,
+    1.0 / 5.0,
+    1.0 / 4.0,
+    0.3,
+    0.4,
+    0.5,
+    0.6,
+    0.8,
+    1.0,
+    1.3,
+    1.6,
+    2.0,
+    2.5,
+    3.2,
+    4.0,
+    5.0,
+    6.0,
+    8.0,
+    10.0,
+    13.0,
+    15.0,
+    20.0,
+    25.0,
+    30.0,
+];
+
+const ISO_FULL: [f32; 11] = [
+    25.0, 50.0, 100.0, 200.0, 400.0, 800.0, 1600.0, 3200.0, 6400.0, 12800.0, 25600.0,
+];
+
+const ISO_HALF: [f32; 21] = [
+    25.0, 35.0, 50.0, 70.0, 100.0, 140.0, 200.0, 280.0, 400.0, 560.0, 800.0, 1100.0, 1600.0,
+    2200.0, 3200.0, 4500.0, 6400.0, 9000.0, 12800.0, 18000.0, 25600.0,
+];
+
+const ISO_THIRD: [f32; 31] = [
+    25.0, 32.0, 40.0, 50.0, 64.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 320.0, 400.0, 500.0,
+    640.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3200.0, 4000.0, 5000.0, 6400.0, 8000.0,
+    10000.0, 12800.0, 16000.0, 20000.0, 25600.0,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALES: [StopScale; 3] = [StopScale::Full, StopScale::Half, StopScale::Third];
+
+    #[test]
+    fn marked_values_snap_to_themselves() {
+        for scale in SCALES {
+            for &aperture in scale.apertures() {
+                assert_eq!(scale.snap_aperture(aperture), aperture);
+            }
+            for &shutter in scale.shutter_speeds() {
+                assert_eq!(scale.snap_shutter(shutter), shutter);
+            }
+            for &iso in scale.iso_speeds() {
+                assert_eq!(scale.snap_iso(iso), iso);
+            }
+        }
+    }
+
+    #[test]
+    fn values_past_the_ends_clamp() {
+        for scale in SCALES {
+            for value in [f32::NAN, f32::NEG_INFINITY, -1.0, 0.0, 1e-9] {
+                assert_eq!(scale.snap_aperture(value), 1.0);
+                assert_eq!(scale.snap_shutter(value), 1.0 / 8000.0);
+                assert_eq!(scale.snap_iso(value), 25.0);
+            }
+            for value in [1e9, f32::INFINITY] {
+                assert_eq!(scale.snap_aperture(value), 64.0);
+                assert_eq!(scale.snap_shutter(value), 30.0);
+                assert_eq!(scale.snap_iso(value), 25600.0);
+            }
+        }
+    }
+
+    #[test]
+    fn values_between_marks_snap_to_the_nearest_stop() {
+        // Halfway in stops lies below the arithmetic midpoint, so these would
+        // snap the other way on a linear scale.
+        assert_eq!(StopScale::Full.snap_aperture(2.35), 2.0);
+        assert_eq!(StopScale::Full.snap_aperture(2.38), 2.8);
+        assert_eq!(StopScale::Full.snap_shutter(1.0 / 90.0), 1.0 / 125.0);
+        assert_eq!(StopScale::Full.snap_shutter(1.0 / 84.0), 1.0 / 60.0);
+        assert_eq!(StopScale::Third.snap_iso(140.0), 125.0);
+        assert_eq!(StopScale::Third.snap_iso(143.0), 160.0);
+
+        assert_eq!(StopScale::Half.snap_aperture(3.0), 2.8);
+        assert_eq!(StopScale::Half.snap_shutter(0.38), 0.3);
+        assert_eq!(StopScale::Third.snap_aperture(5.9), 5.6);
+        assert_eq!(StopScale::Third.snap_shutter(0.44), 0.4);
+    }
+}
@@ -0,0 +1,354 @@
+//! Fit [`AmsLuxConverter`] coefficients to readings taken next to a reference
+//! lux meter.
+//!
+//! Runs on the host (requires the `std` feature); the resulting
+//! [`LuxCoefficients`] are plain data and can be used in `no_std` firmware.
+
+use crate::lux_conversion::{check_overflow, AmsLuxConverter, DynLuxConverter, LuxCoefficients};
+use crate::types::{Gain, IntegrationTime};
+use std::vec::Vec;
+
+/// Assignment passes of the two-equation fit before giving up on convergence.
+const MAX_ITERATIONS: usize = 20;
+
+/// One reading taken alongside a reference meter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub ch_0: u16,
+    pub ch_1: u16,
+    pub gain: Gain,
+    pub integration_time: IntegrationTime,
+    pub reference_lux: f32,
+}
+
+impl From<(u16, u16, Gain, IntegrationTime, f32)> for Sample {
+    fn from(
+        (ch_0, ch_1, gain, integration_time, reference_lux): (u16, u16, Gain, IntegrationTime, f32),
+    ) -> Self {
+        Sample {
+            ch_0,
+            ch_1,
+            gain,
+            integration_time,
+            reference_lux,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitError {
+    /// Fewer than one usable sample (not saturated, positive reference lux)
+    NotEnoughSamples,
+    /// The samples do not determine a positive device factor
+    Degenerate,
+}
+
+/// Result of [`fit`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitReport {
+    /// Fitted coefficients. Glass attenuation is folded into the device
+    /// factor, so `glass_attenuation` is always 1.0.
+    pub coefficients: LuxCoefficients,
+    /// Predicted minus reference lux per input sample, `None` for samples that
+    /// were rejected (saturated or non-positive reference)
+    pub residuals: Vec<Option<f32>>,
+    /// Root-mean-square error in lux
+    pub rms_error: f32,
+    /// Root-mean-square of residual / reference
+    pub rms_relative_error: f32,
+    pub max_relative_error: f32,
+    /// Coefficient of determination of the predictions
+    pub r_squared: f32,
+    pub rejected: usize,
+}
+
+impl FitReport {
+    pub fn converter(&self) -> AmsLuxConverter {
+        AmsLuxConverter::new(self.coefficients)
+    }
+}
+
+struct Point {
+    ch_0: f64,
+    ch_1: f64,
+    /// Reference lux times ATIME times AGAIN, i.e. DF * max(...) in the model
+    y: f64,
+}
+
+/// Weighted least squares of `y = a * ch_0 + b * ch_1`, weighted by `1 / y^2`
+/// so every sample contributes its relative error.
+fn solve_two(points: &[&Point]) -> Option<(f64, f64)> {
+    let (mut s00, mut s01, mut s11, mut s0y, mut s1y) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for p in points {
+        let w = 1.0 / (p.y * p.y);
+        s00 += w * p.ch_0 * p.ch_0;
+        s01 += w * p.ch_0 * p.ch_1;
+        s11 += w * p.ch_1 * p.ch_1;
+        s0y += w * p.ch_0 * p.y;
+        s1y += w * p.ch_1 * p.y;
+    }
+    let det = s00 * s11 - s01 * s01;
+    if det.abs() <= 1e-12 * s00 * s11 {
+        return None;
+    }
+    Some(((s0y * s11 - s1y * s01) / det, (s1y * s00 - s0y * s01) / det))
+}
+
+fn equations(c: &LuxCoefficients, p: &Point) -> (f64, f64) {
+    (
+        p.ch_0 - c.coef_b as f64 * p.ch_1,
+        c.coef_c as f64 * p.ch_0 - c.coef_d as f64 * p.ch_1,
+    )
+}
+
+/// Fit only the device factor, keeping the channel coefficients of `shape`.
+fn fit_scale(points: &[Point], shape: &LuxCoefficients) -> Option<LuxCoefficients> {
+    let (mut sxy, mut sxx) = (0.0, 0.0);
+    for p in points {
+        let (lux1, lux2) = equations(shape, p);
+        let x = f64::max(lux1, lux2);
+        let w = 1.0 / (p.y * p.y);
+        sxy += w * x * p.y;
+        sxx += w * x * x;
+    }
+    let device_factor = sxy / sxx;
+    if device_factor.is_nan() || device_factor <= 0.0 {
+        return None;
+    }
+    Some(LuxCoefficients {
+        device_factor: device_factor as f32,
+        glass_attenuation: 1.0,
+        ..*shape
+    })
+}
+
+/// Fit both AMS equations, alternating between assigning each sample to the
+/// equation that dominates it and refitting each equation by least squares.
+fn fit_two(points: &[Point], initial: &LuxCoefficients) -> Option<LuxCoefficients> {
+    let mut coefficients = *initial;
+    let mut assignment: Vec<bool> = Vec::new();
+    for _ in 0..MAX_ITERATIONS {
+        let next: Vec<bool> = points
+            .iter()
+            .map(|p| {
+                let (lux1, lux2) = equations(&coefficients, p);
+                lux2 > lux1
+            })
+            .collect();
+        if next == assignment {
+            break;
+        }
+        assignment = next;
+
+        let first: Vec<&Point> = points
+            .iter()
+            .zip(&assignment)
+            .filter(|(_, &second)| !second)
+            .map(|(p, _)| p)
+            .collect();
+        let second: Vec<&Point> = points
+            .iter()
+            .zip(&assignment)
+            .filter(|(_, &second)| second)
+            .map(|(p, _)| p)
+            .collect();
+
+        let (a1, b1) = solve_two(&first)?;
+        if a1 <= 0.0 {
+            return None;
+        }
+        let (coef_c, coef_d) = match solve_two(&second) {
+            Some((a2, b2)) => ((a2 / a1) as f32, (-b2 / a1) as f32),
+            // Too few or collinear samples for the second equation: disable it
+            None => (0.0, 0.0),
+        };
+        coefficients = LuxCoefficients {
+            device_factor: a1 as f32,
+            glass_attenuation: 1.0,
+            coef_b: (-b1 / a1) as f32,
+            coef_c,
+            coef_d,
+        };
+    }
+    Some(coefficients)
+}
+
+/// Fit AMS converter coefficients to `samples`.
+///
+/// `initial` provides the starting point, and the channel coefficients used
+/// when the samples cannot determine them (e.g. all taken under one light
+/// source, so CH1/CH0 is constant); in that case only the device factor is
+/// fitted. Saturated samples are rejected. Errors are minimised relative to
+/// the reference reading, so dim and bright samples count equally.
+pub fn fit(samples: &[Sample], initial: &LuxCoefficients) -> Result<FitReport, FitError> {
+    let usable =
+        |s: &Sample| s.reference_lux > 0.0 && !check_overflow(s.integration_time, s.ch_0, s.ch_1);
+    let points: Vec<Point> = samples
+        .iter()
+        .filter(|s| usable(s))
+        .map(|s| Point {
+            ch_0: s.ch_0 as f64,
+            ch_1: s.ch_1 as f64,
+            y: s.reference_lux as f64
+                * s.integration_time.get_integration_time_millis() as f64
+                * s.gain.get_multiplier() as f64,
+        })
+        .collect();
+    if points.is_empty() {
+        return Err(FitError::NotEnoughSamples);
+    }
+
+    let coefficients = fit_two(&points, initial)
+        .or_else(|| fit_scale(&points, initial))
+        .ok_or(FitError::Degenerate)?;
+    let converter = AmsLuxConverter::new(coefficients);
+
+    let residuals: Vec<Option<f32>> = samples
+        .iter()
+        .map(|s| {
+            if !usable(s) {
+                return None;
+            }
+            converter
+                .lux(s.integration_time, s.gain, s.ch_0, s.ch_1)
+                .map(|lux| lux - s.reference_lux)
+        })
+        .collect();
+
+    let n = points.len() as f64;
+    let mean = samples
+        .iter()
+        .filter(|s| usable(s))
+        .map(|s| s.reference_lux as f64)
+        .sum::<f64>()
+        / n;
+    let (mut ss_res, mut ss_tot, mut ss_rel, mut max_rel) = (0.0, 0.0, 0.0, 0.0f64);
+    for (s, r) in samples.iter().zip(&residuals) {
+        if let Some(r) = r {
+            let r = *r as f64;
+            let reference = s.reference_lux as f64;
+            let rel = r / reference;
+            ss_res += r * r;
+            ss_tot += (reference - mean) * (reference - mean);
+            ss_rel += rel * rel;
+            max_rel = max_rel.max(rel.abs());
+        }
+    }
+
+    Ok(FitReport {
+        coefficients,
+        rms_error: (ss_res / n).sqrt() as f32,
+        rms_relative_error: (ss_rel / n).sqrt() as f32,
+        max_relative_error: max_rel as f32,
+        r_squared: if ss_tot > 0.0 {
+            (1.0 - ss_res / ss_tot) as f32
+        } else {
+            1.0
+        },
+        rejected: samples.len() - points.len(),
+        residuals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRUE: LuxCoefficients = LuxCoefficients {
+        device_factor: 350.0,
+        glass_attenuation: 1.0,
+        coef_b: 1.7,
+        coef_c: 0.62,
+        coef_d: 0.9,
+    };
+
+    fn synthetic(coefficients: &LuxCoefficients, ratios: &[f32]) -> Vec<Sample> {
+        let converter = AmsLuxConverter::new(*coefficients);
+        let mut samples = Vec::new();
+        for &(gain, integration_time) in &[
+            (Gain::Low, IntegrationTime::_100MS),
+            (Gain::Med, IntegrationTime::_200MS),
+            (Gain::High, IntegrationTime::_600MS),
+        ] {
+            for &ch_0 in &[800u16, 5_000, 20_000] {
+                for &ratio in ratios {
+                    let ch_1 = (ch_0 as f32 * ratio) as u16;
+                    let reference_lux = converter.lux(integration_time, gain, ch_0, ch_1).unwrap();
+                    samples.push((ch_0, ch_1, gain, integration_time, reference_lux).into());
+                }
+            }
+        }
+        samples
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-2 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn recovers_known_coefficients() {
+        let samples = synthetic(&TRUE, &[0.05, 0.15, 0.25, 0.35, 0.45, 0.55, 0.6, 0.62]);
+        let report = fit(&samples, &LuxCoefficients::DN40).unwrap();
+        let c = report.coefficients;
+        assert!(close(c.device_factor, TRUE.device_factor), "{:?}", c);
+        assert!(close(c.coef_b, TRUE.coef_b), "{:?}", c);
+        assert!(close(c.coef_c, TRUE.coef_c), "{:?}", c);
+        assert!(close(c.coef_d, TRUE.coef_d), "{:?}", c);
+        assert!(report.rms_relative_error < 1e-3);
+        assert!(report.r_squared > 0.999);
+        assert_eq!(report.rejected, 0);
+    }
+
+    #[test]
+    fn single_source_fits_device_factor_only() {
+        let samples = synthetic(
+            &LuxCoefficients {
+                device_factor: 300.0,
+                ..LuxCoefficients::DN40
+            },
+            &[0.2],
+        );
+        let report = fit(&samples, &LuxCoefficients::DN40).unwrap();
+        assert!(close(report.coefficients.device_factor, 300.0));
+        assert_eq!(report.coefficients.coef_b, LuxCoefficients::DN40.coef_b);
+    }
+
+    #[test]
+    fn rejects_saturated_samples() {
+        let mut samples = synthetic(&TRUE, &[0.05, 0.3, 0.55, 0.62]);
+        let saturated = Sample {
+            ch_0: IntegrationTime::_100MS.max_count(),
+            ch_1: 100,
+            gain: Gain::Low,
+            integration_time: IntegrationTime::_100MS,
+            // Far off, so accepting it would spoil the fit
+            reference_lux: 1.0,
+        };
+        samples.push(saturated);
+        samples.push(Sample {
+            reference_lux: 0.0,
+            ..samples[0]
+        });
+
+        let report = fit(&samples, &LuxCoefficients::DN40).unwrap();
+        assert_eq!(report.rejected, 2);
+        assert_eq!(report.residuals[samples.len() - 2], None);
+        assert_eq!(report.residuals[samples.len() - 1], None);
+        assert!(close(report.coefficients.device_factor, TRUE.device_factor));
+    }
+
+    #[test]
+    fn no_usable_samples() {
+        let saturated = Sample {
+            ch_0: u16::MAX,
+            ch_1: 0,
+            gain: Gain::Low,
+            integration_time: IntegrationTime::_200MS,
+            reference_lux: 100.0,
+        };
+        assert_eq!(
+            fit(&[saturated], &LuxCoefficients::DN40).unwrap_err(),
+            FitError::NotEnoughSamples
+        );
+    }
+}
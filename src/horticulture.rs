@@ -0,0 +1,271 @@
+//! Photosynthetic photon flux density and Daily Light Integral.
+
+use crate::light_source::LightSource;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Light source assumed when converting lux to PPFD.
+///
+/// Factors are µmol/m²/s per lux, after Thimijan and Heins (1983). Narrow-band
+/// grow lights differ strongly from white light; measure them against a
+/// quantum sensor and use [`Custom`](PhotonSource::Custom).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhotonSource {
+    Sunlight,
+    Incandescent,
+    Fluorescent,
+    HighPressureSodium,
+    MetalHalide,
+    WhiteLed,
+    /// µmol/m²/s per lux
+    Custom(f32),
+}
+
+impl PhotonSource {
+    /// µmol/m²/s per lux
+    pub fn factor(&self) -> f32 {
+        match *self {
+            PhotonSource::Sunlight => 0.0185,
+            PhotonSource::Incandescent => 0.0200,
+            PhotonSource::Fluorescent => 0.0135,
+            PhotonSource::HighPressureSodium => 0.0122,
+            PhotonSource::MetalHalide => 0.0141,
+            PhotonSource::WhiteLed => 0.0150,
+            PhotonSource::Custom(factor) => factor,
+        }
+    }
+
+    /// PPFD in µmol/m²/s.
+    pub fn ppfd(&self, lux: f32) -> f32 {
+        lux.max(0.0) * self.factor()
+    }
+}
+
+impl From<LightSource> for PhotonSource {
+    fn from(source: LightSource) -> Self {
+        match source {
+            LightSource::Led => PhotonSource::WhiteLed,
+            LightSource::Fluorescent => PhotonSource::Fluorescent,
+            LightSource::Sunlight => PhotonSource::Sunlight,
+            LightSource::Incandescent => PhotonSource::Incandescent,
+        }
+    }
+}
+
+/// Light accumulated over one day.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DailyLightIntegral {
+    /// Day number, counted from timestamp zero plus the day start offset
+    pub day: u64,
+    /// mol/m²
+    pub dli: f32,
+    /// Seconds of the day covered by readings no further apart than the
+    /// maximum gap
+    pub covered_seconds: u32,
+}
+
+impl DailyLightIntegral {
+    /// Fraction of the day covered by readings.
+    pub fn coverage(&self) -> f32 {
+        self.covered_seconds as f32 / SECONDS_PER_DAY as f32
+    }
+}
+
+/// Accumulates Daily Light Integral from timestamped PPFD readings.
+///
+/// Consecutive readings are integrated with the trapezoidal rule. Intervals
+/// longer than the maximum gap are not integrated, and show up as reduced
+/// [`coverage`](DailyLightIntegral::coverage) instead. An interval spanning
+/// the day boundary is split there, and [`add`](Self::add) returns the day it
+/// completed. Readings older than the previous one are ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DliIntegrator {
+    max_gap_seconds: u32,
+    day_start_offset: u32,
+    day: u64,
+    micromoles: f64,
+    covered_seconds: u32,
+    last: Option<(u64, f32)>,
+}
+
+impl DliIntegrator {
+    /// `day_start_offset` is the number of seconds after timestamp midnight
+    /// at which a day starts, e.g. a UTC offset or the start of the
+    /// photoperiod.
+    pub fn new(max_gap_seconds: u32, day_start_offset: u32) -> Self {
+        DliIntegrator {
+            max_gap_seconds,
+            day_start_offset: day_start_offset % SECONDS_PER_DAY as u32,
+            day: 0,
+            micromoles: 0.0,
+            covered_seconds: 0,
+            last: None,
+        }
+    }
+
+    fn day_of(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_sub(self.day_start_offset as u64) / SECONDS_PER_DAY
+    }
+
+    fn day_end(&self) -> u64 {
+        (self.day + 1) * SECONDS_PER_DAY + self.day_start_offset as u64
+    }
+
+    /// Add a reading taken at `timestamp` seconds with `ppfd` µmol/m²/s.
+    /// Returns the previous day's total when this reading starts a new day.
+    pub fn add(&mut self, timestamp: u64, ppfd: f32) -> Option<DailyLightIntegral> {
+        let ppfd = ppfd.max(0.0);
+        let (mut start, mut start_ppfd) = match self.last {
+            Some(last) => last,
+            None => {
+                self.day = self.day_of(timestamp);
+                self.last = Some((timestamp, ppfd));
+                return None;
+            }
+        };
+        if timestamp <= start {
+            return None;
+        }
+
+        let gap = timestamp - start > self.max_gap_seconds as u64;
+        let mut completed = None;
+        loop {
+            let boundary = self.day_end();
+            if timestamp < boundary {
+                self.integrate(start, start_ppfd, timestamp, ppfd, gap);
+                break;
+            }
+            let boundary_ppfd = start_ppfd
+                + (ppfd - start_ppfd) * (boundary - start) as f32 / (timestamp - start) as f32;
+            self.integrate(start, start_ppfd, boundary, boundary_ppfd, gap);
+            completed = Some(self.current());
+            self.micromoles = 0.0;
+            self.covered_seconds = 0;
+            // Days without any reading are skipped rather than reported empty.
+            self.day = if gap {
+                self.day_of(timestamp)
+            } else {
+                self.day + 1
+            };
+            start = boundary.max(self.day * SECONDS_PER_DAY + self.day_start_offset as u64);
+            start_ppfd = boundary_ppfd;
+        }
+
+        self.last = Some((timestamp, ppfd));
+        completed
+    }
+
+    fn integrate(&mut self, start: u64, start_ppfd: f32, end: u64, end_ppfd: f32, gap: bool) {
+        if gap || end <= start {
+            return;
+        }
+        let seconds = end - start;
+        self.micromoles += (start_ppfd as f64 + end_ppfd as f64) / 2.0 * seconds as f64;
+        self.covered_seconds += seconds as u32;
+    }
+
+    /// Total of the day in progress.
+    pub fn current(&self) -> DailyLightIntegral {
+        DailyLightIntegral {
+            day: self.day,
+            dli: (self.micromoles / 1_000_000.0) as f32,
+            covered_seconds: self.covered_seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3600;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected.abs() * 1e-5 + 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn day_rolls_over_at_the_offset() {
+        let mut dli = DliIntegrator::new(900, HOUR as u32);
+        let start = HOUR;
+        for timestamp in (start..start + SECONDS_PER_DAY).step_by(600) {
+            assert_eq!(dli.add(timestamp, 100.0), None);
+        }
+        // Midnight of the timestamps is still inside the first day.
+        assert_eq!(dli.current().day, 0);
+
+        let completed = dli.add(start + SECONDS_PER_DAY, 100.0).unwrap();
+        assert_eq!(completed.day, 0);
+        assert_eq!(completed.covered_seconds, SECONDS_PER_DAY as u32);
+        assert_close(completed.dli, 8.64);
+        assert_close(completed.coverage(), 1.0);
+
+        let current = dli.current();
+        assert_eq!(current.day, 1);
+        assert_eq!(current.covered_seconds, 0);
+    }
+
+    #[test]
+    fn interval_across_the_boundary_is_split() {
+        let mut dli = DliIntegrator::new(900, HOUR as u32);
+        let boundary = SECONDS_PER_DAY + HOUR;
+        assert_eq!(dli.add(boundary - 300, 100.0), None);
+        let completed = dli.add(boundary + 300, 300.0).unwrap();
+        // 200 µmol/m²/s at the boundary
+        assert_eq!(completed.covered_seconds, 300);
+        assert_close(completed.dli, 150.0 * 300.0 / 1e6);
+
+        let current = dli.current();
+        assert_eq!(current.day, 1);
+        assert_eq!(current.covered_seconds, 300);
+        assert_close(current.dli, 250.0 * 300.0 / 1e6);
+    }
+
+    #[test]
+    fn gaps_are_not_integrated() {
+        let mut dli = DliIntegrator::new(900, 0);
+        dli.add(0, 100.0);
+        dli.add(600, 100.0);
+        dli.add(600 + 901, 100.0);
+        dli.add(600 + 901 + 900, 100.0);
+        let current = dli.current();
+        assert_eq!(current.covered_seconds, 1500);
+        assert_close(current.dli, 100.0 * 1500.0 / 1e6);
+    }
+
+    #[test]
+    fn gap_over_several_days_skips_them() {
+        let mut dli = DliIntegrator::new(900, 0);
+        dli.add(HOUR, 100.0);
+        dli.add(HOUR + 600, 100.0);
+        let completed = dli.add(3 * SECONDS_PER_DAY + HOUR, 100.0).unwrap();
+        assert_eq!(completed.day, 0);
+        assert_eq!(completed.covered_seconds, 600);
+
+        let current = dli.current();
+        assert_eq!(current.day, 3);
+        assert_eq!(current.covered_seconds, 0);
+        assert_eq!(dli.add(3 * SECONDS_PER_DAY + HOUR + 600, 100.0), None);
+        assert_eq!(dli.current().covered_seconds, 600);
+    }
+
+    #[test]
+    fn out_of_order_readings_are_ignored() {
+        let mut dli = DliIntegrator::new(900, 0);
+        dli.add(1000, 100.0);
+        dli.add(1600, 100.0);
+        let before = dli;
+        assert_eq!(dli.add(1600, 5000.0), None);
+        assert_eq!(dli.add(1200, 5000.0), None);
+        assert_eq!(dli, before);
+
+        // Integration continues from the last accepted reading.
+        dli.add(2200, 100.0);
+        assert_eq!(dli.current().covered_seconds, 1200);
+    }
+}
@@ -0,0 +1,208 @@
+//! Irradiance estimation from raw channel data.
+
+use crate::{
+    lux_conversion::check_overflow,
+    types::{Gain, IntegrationTime},
+};
+
+/// Irradiance split into the parts the two channels can tell apart, in W/m².
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Irradiance {
+    /// Irradiance attributed to the visible reference source
+    pub visible: f32,
+    /// Irradiance attributed to the infrared reference source
+    pub infrared: f32,
+    /// Estimated broadband irradiance, `(visible + infrared) * broadband_factor`
+    pub total: f32,
+}
+
+/// Spectral assumptions for converting counts to irradiance.
+///
+/// The responsivities describe how strongly each channel responds to a visible
+/// (white light) and an infrared (850 nm) reference source, in counts per
+/// µW/cm² at the reference gain and integration time. Channel data is treated
+/// as a mix of the two sources, which are solved for separately.
+///
+/// The sensor only sees roughly 400 nm to 1100 nm; `broadband_factor` scales
+/// the in-band result up to whatever band the application reports, e.g. the
+/// full solar spectrum for pyranometer-style readings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpectralModel {
+    /// CH0 responsivity to the visible source
+    pub ch_0_visible: f32,
+    /// CH1 responsivity to the visible source
+    pub ch_1_visible: f32,
+    /// CH0 responsivity to the infrared source
+    pub ch_0_infrared: f32,
+    /// CH1 responsivity to the infrared source
+    pub ch_1_infrared: f32,
+    /// Gain the responsivities were measured at
+    pub reference_gain: Gain,
+    /// Integration time the responsivities were measured at
+    pub reference_integration_time: IntegrationTime,
+    pub broadband_factor: f32,
+}
+
+impl SpectralModel {
+    /// Typical responsivities from the TSL2591 datasheet, reporting in-band
+    /// irradiance.
+    pub const DATASHEET: Self = SpectralModel {
+        ch_0_visible: 264.1,
+        ch_1_visible: 34.9,
+        ch_0_infrared: 257.5,
+        ch_1_infrared: 154.1,
+        reference_gain: Gain::High,
+        reference_integration_time: IntegrationTime::_100MS,
+        broadband_factor: 1.0,
+    };
+
+    /// Estimate irradiance with the nominal gain multipliers. Returns `None` if
+    /// the data overflowed or the responsivities are degenerate.
+    pub fn irradiance(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<Irradiance> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            return None;
+        }
+
+        // Counts the reference settings would have produced
+        let scale = (self.reference_gain.get_multiplier() as f32
+            * self
+                .reference_integration_time
+                .get_integration_time_millis() as f32)
+            / (gain.get_multiplier() as f32
+                * integration_time.get_integration_time_millis() as f32);
+        let ch_0 = ch_0 as f32 * scale;
+        let ch_1 = ch_1 as f32 * scale;
+
+        let determinant =
+            self.ch_0_visible * self.ch_1_infrared - self.ch_0_infrared * self.ch_1_visible;
+        if determinant == 0.0 || determinant.is_nan() {
+            return None;
+        }
+        // µW/cm², clamped since noise can push either part below zero
+        let visible =
+            ((ch_0 * self.ch_1_infrared - ch_1 * self.ch_0_infrared) / determinant).max(0.0);
+        let infrared =
+            ((ch_1 * self.ch_0_visible - ch_0 * self.ch_1_visible) / determinant).max(0.0);
+
+        // 1 µW/cm² = 0.01 W/m²
+        let visible = visible * 0.01;
+        let infrared = infrared * 0.01;
+        Some(Irradiance {
+            visible,
+            infrared,
+            total: (visible + infrared) * self.broadband_factor,
+        })
+    }
+}
+
+impl Default for SpectralModel {
+    fn default() -> Self {
+        Self::DATASHEET
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: SpectralModel = SpectralModel::DATASHEET;
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn reproduces_datasheet_responsivity() {
+        // 100 µW/cm² (1 W/m²) of each reference source at High and 100 ms
+        let visible = MODEL
+            .irradiance(IntegrationTime::_100MS, Gain::High, 26_410, 3490)
+            .unwrap();
+        assert_close(visible.visible, 1.0, 1e-4);
+        assert_close(visible.infrared, 0.0, 1e-4);
+        assert_close(visible.total, 1.0, 1e-4);
+
+        let infrared = MODEL
+            .irradiance(IntegrationTime::_100MS, Gain::High, 25_750, 15_410)
+            .unwrap();
+        assert_close(infrared.visible, 0.0, 1e-4);
+        assert_close(infrared.infrared, 1.0, 1e-4);
+
+        // Half of each adds up
+        let mixed = MODEL
+            .irradiance(IntegrationTime::_100MS, Gain::High, 26_080, 9450)
+            .unwrap();
+        assert_close(mixed.visible, 0.5, 1e-4);
+        assert_close(mixed.infrared, 0.5, 1e-4);
+        assert_close(mixed.total, 1.0, 1e-4);
+    }
+
+    #[test]
+    fn rescales_gain_and_integration_time() {
+        // The visible reading above at 25x instead of 428x, and 300 ms
+        let ch_0 = 26_410.0 * 25.0 / 428.0 * 3.0;
+        let ch_1 = 3490.0 * 25.0 / 428.0 * 3.0;
+        let irradiance = MODEL
+            .irradiance(
+                IntegrationTime::_300MS,
+                Gain::Med,
+                (ch_0 + 0.5) as u16,
+                (ch_1 + 0.5) as u16,
+            )
+            .unwrap();
+        assert_close(irradiance.visible, 1.0, 1e-3);
+        assert_close(irradiance.infrared, 0.0, 1e-3);
+
+        // A hundredth of the light at 9876x, about 23 times the gain of 428x
+        let irradiance = MODEL
+            .irradiance(IntegrationTime::_100MS, Gain::Max, 6094, 805)
+            .unwrap();
+        assert_close(irradiance.visible, 0.01, 1e-5);
+    }
+
+    #[test]
+    fn broadband_factor_scales_total_only() {
+        let model = SpectralModel {
+            broadband_factor: 2.5,
+            ..MODEL
+        };
+        let irradiance = model
+            .irradiance(IntegrationTime::_100MS, Gain::High, 26_080, 9450)
+            .unwrap();
+        assert_close(irradiance.visible, 0.5, 1e-4);
+        assert_close(irradiance.total, 2.5, 1e-3);
+    }
+
+    #[test]
+    fn rejects_overflow_and_degenerate_models() {
+        assert_eq!(
+            MODEL.irradiance(IntegrationTime::_100MS, Gain::High, 36_863, 0),
+            None
+        );
+        let degenerate = SpectralModel {
+            ch_0_infrared: 264.1,
+            ch_1_infrared: 34.9,
+            ..MODEL
+        };
+        assert_eq!(
+            degenerate.irradiance(IntegrationTime::_100MS, Gain::High, 1000, 100),
+            None
+        );
+        // More CH1 than either source explains clamps visible at zero
+        let irradiance = MODEL
+            .irradiance(IntegrationTime::_100MS, Gain::High, 1000, 900)
+            .unwrap();
+        assert_eq!(irradiance.visible, 0.0);
+        assert!(irradiance.infrared > 0.0);
+    }
+}
@@ -21,18 +21,55 @@
 
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+mod backlight;
+mod calibration;
 mod chip;
+mod config;
+mod diagnostics;
 mod error;
+pub mod exposure;
+#[cfg(feature = "std")]
+pub mod fit;
+mod horticulture;
+mod irradiance;
+mod light_source;
 mod lux_conversion;
+pub mod power;
+mod quality;
 mod sensor_impl;
+mod sqm;
+mod state;
+#[cfg(feature = "storage")]
+pub mod storage;
 mod types;
 
+pub use backlight::{BacklightController, BrightnessCurve};
+pub use calibration::{
+    DarkCompensated, DarkOffsets, GainCalibration, TemperatureCompensated, TemperatureModel,
+};
+pub use config::{Config, ConfigBuilder, ConfigError};
+pub use diagnostics::{RegisterSnapshot, SelfTestReport};
 pub use error::Error;
+pub use horticulture::{DailyLightIntegral, DliIntegrator, PhotonSource};
+pub use irradiance::{Irradiance, SpectralModel};
+pub use light_source::{
+    Classification, LightSource, LightSourceClassifier, SourceAdaptiveConverter,
+};
 pub use lux_conversion::{
-    check_overflow, AdafruitPythonLuxConverter, LuxConverter, YoctoLuxConverter,
+    check_overflow, AdafruitPythonLuxConverter, AmsLuxConverter, AmsLuxConverterSunlight,
+    DynLuxConverter, LuxAlgorithm, LuxCoefficients, LuxConverter, YoctoLuxConverter,
 };
+pub use quality::{LuxQuality, QualityFlags, QualityModel};
 pub use sensor_impl::Tsl2591;
-pub use types::{Enable, Gain, IntegrationTime, Mode, Status};
+pub use sqm::{SkyQuality, SqmConfig};
+pub use state::{Measure, Off, On, PowerState, Untracked};
+pub use types::{
+    Control, DeviceInfo, Enable, Gain, IntegrationTime, LuxEstimate, Measurement, Mode,
+    Persistence, Status,
+};
 
 #[cfg(not(any(feature = "blocking", feature = "async")))]
 compile_error!("You must enable exactly one of the following features: `blocking`, `async`");
@@ -0,0 +1,291 @@
+//! Light source classification from the CH1/CH0 ratio.
+
+use crate::{
+    lux_conversion::{AmsLuxConverter, DynLuxConverter, LuxCoefficients},
+    types::{Gain, IntegrationTime},
+};
+
+/// Broad light source families, told apart by their infrared content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightSource {
+    Led,
+    Fluorescent,
+    Sunlight,
+    Incandescent,
+}
+
+impl LightSource {
+    pub const ALL: [LightSource; 4] = [
+        LightSource::Led,
+        LightSource::Fluorescent,
+        LightSource::Sunlight,
+        LightSource::Incandescent,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            LightSource::Led => 0,
+            LightSource::Fluorescent => 1,
+            LightSource::Sunlight => 2,
+            LightSource::Incandescent => 3,
+        }
+    }
+}
+
+/// Result of [`LightSourceClassifier::classify`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Classification {
+    pub source: LightSource,
+    /// Between 0 and 1; how clearly the ratio matches `source` rather than
+    /// its neighbours
+    pub confidence: f32,
+    /// Measured CH1/CH0
+    pub ir_ratio: f32,
+}
+
+/// Nearest-match classifier over typical CH1/CH0 ratios.
+///
+/// Ratios are compared on a logarithmic scale, since IR content varies by
+/// orders of magnitude between white LEDs and incandescent lamps. Confidence is
+/// the share of a Gaussian match score, so a ratio halfway between two
+/// profiles gives roughly 0.5.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSourceClassifier {
+    /// Typical CH1/CH0 per source, indexed like [`LightSource::ALL`]
+    pub ir_ratios: [f32; 4],
+    /// Width of the match score in natural-log units of the ratio
+    pub width: f32,
+    /// Smallest CH0 count to attempt a classification
+    pub min_counts: u16,
+}
+
+impl LightSourceClassifier {
+    pub const DEFAULT: Self = LightSourceClassifier {
+        ir_ratios: [0.05, 0.12, 0.3, 0.6],
+        width: 0.4,
+        min_counts: 50,
+    };
+
+    pub fn ir_ratio(&self, source: LightSource) -> f32 {
+        self.ir_ratios[source.index()]
+    }
+
+    /// Classify raw channel data. Returns `None` when CH0 is too weak for the
+    /// ratio to mean anything.
+    pub fn classify(&self, ch_0: u16, ch_1: u16) -> Option<Classification> {
+        if ch_0 < self.min_counts.max(1) {
+            return None;
+        }
+        let weights = self.weights(ch_0, ch_1);
+        let mut best = 0;
+        for (index, &weight) in weights.iter().enumerate() {
+            if weight > weights[best] {
+                best = index;
+            }
+        }
+        Some(Classification {
+            source: LightSource::ALL[best],
+            confidence: weights[best],
+            ir_ratio: ch_1 as f32 / ch_0 as f32,
+        })
+    }
+
+    /// Share of the match score of each source, indexed like
+    /// [`LightSource::ALL`] and summing to 1. Unlike
+    /// [`classify`](Self::classify) this ignores `min_counts`, and it changes
+    /// smoothly with the ratio.
+    pub fn weights(&self, ch_0: u16, ch_1: u16) -> [f32; 4] {
+        let ir_ratio = ch_1 as f32 / ch_0.max(1) as f32;
+        // Keep the logarithm finite in IR-free light.
+        let log_ratio = libm::logf(ir_ratio.max(1e-3));
+
+        let mut distances = [0.0f32; 4];
+        for (distance, &source) in distances.iter_mut().zip(LightSource::ALL.iter()) {
+            let d = (log_ratio - libm::logf(self.ir_ratio(source))) / self.width;
+            *distance = 0.5 * d * d;
+        }
+        // Score relative to the nearest profile so ratios far outside every
+        // profile don't underflow to all zeros.
+        let nearest = distances.iter().copied().fold(f32::INFINITY, f32::min);
+        let mut weights = distances.map(|distance| libm::expf(nearest - distance));
+        let total: f32 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight /= total;
+        }
+        weights
+    }
+}
+
+impl Default for LightSourceClassifier {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// AMS converter blending per-source coefficients by the classified light
+/// source of each reading.
+///
+/// The result is the average of the per-source lux values, weighted by
+/// [`LightSourceClassifier::weights`], so it changes smoothly when the ratio
+/// moves from one source to the next instead of jumping at the class
+/// boundary. The driver ships no per-source coefficients on a common scale:
+/// supply your own, calibrated against the same reference meter for each
+/// source, e.g. with the `fit` module (`std` feature). With the same
+/// coefficients for every source, it is a plain [`AmsLuxConverter`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SourceAdaptiveConverter {
+    pub classifier: LightSourceClassifier,
+    converters: [AmsLuxConverter; 4],
+}
+
+impl SourceAdaptiveConverter {
+    /// `coefficients` are indexed like [`LightSource::ALL`].
+    pub fn new(classifier: LightSourceClassifier, coefficients: [LuxCoefficients; 4]) -> Self {
+        SourceAdaptiveConverter {
+            classifier,
+            converters: coefficients.map(AmsLuxConverter::new),
+        }
+    }
+
+    pub fn coefficients(&self, source: LightSource) -> LuxCoefficients {
+        self.converters[source.index()].coefficients()
+    }
+
+    pub fn set_coefficients(&mut self, source: LightSource, coefficients: LuxCoefficients) {
+        self.converters[source.index()] = AmsLuxConverter::new(coefficients);
+    }
+}
+
+impl DynLuxConverter for SourceAdaptiveConverter {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64> {
+        let weights = self.classifier.weights(ch_0, ch_1);
+        let mut nano_lux = 0.0f64;
+        for (converter, &weight) in self.converters.iter().zip(weights.iter()) {
+            nano_lux +=
+                converter.nano_lux(integration_time, gain, ch_0, ch_1)? as f64 * weight as f64;
+        }
+        Some((nano_lux + 0.5) as i64)
+    }
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        let weights = self.classifier.weights(ch_0, ch_1);
+        let mut lux = 0.0;
+        for (converter, &weight) in self.converters.iter().zip(weights.iter()) {
+            lux += converter.lux(integration_time, gain, ch_0, ch_1)? * weight;
+        }
+        Some(lux)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(converter: &SourceAdaptiveConverter) -> impl Iterator<Item = (u16, f32, f32)> + '_ {
+        // CH1/CH0 from 0.02 to 0.5, crossing every class boundary.
+        (20..500).map(move |ch_1| {
+            let lux = |ch_1| {
+                converter
+                    .lux(IntegrationTime::_200MS, Gain::Low, 1000, ch_1)
+                    .unwrap()
+            };
+            (ch_1, lux(ch_1), lux(ch_1 + 1))
+        })
+    }
+
+    #[test]
+    fn uniform_coefficients_match_the_plain_converter() {
+        let converter = SourceAdaptiveConverter::new(
+            LightSourceClassifier::DEFAULT,
+            [LuxCoefficients::DN40; 4],
+        );
+        let dn40 = AmsLuxConverter::new(LuxCoefficients::DN40);
+        for (ch_1, lux, _) in sweep(&converter) {
+            let expected = dn40
+                .lux(IntegrationTime::_200MS, Gain::Low, 1000, ch_1)
+                .unwrap();
+            assert!((lux - expected).abs() <= expected * 1e-5, "ch1 {}", ch_1);
+        }
+    }
+
+    #[test]
+    fn calibrated_sources_blend_across_classes() {
+        let coefficients = [340.0, 380.0, 440.0, 520.0].map(|device_factor| LuxCoefficients {
+            device_factor,
+            ..LuxCoefficients::DN40
+        });
+        let converter = SourceAdaptiveConverter::new(LightSourceClassifier::DEFAULT, coefficients);
+        assert_eq!(
+            converter.coefficients(LightSource::Sunlight).device_factor,
+            440.0
+        );
+        // One count of CH1 moves plain DN40 by up to about 1%; picking a
+        // single source per reading would jump by over 15% here.
+        for (ch_1, lux, next) in sweep(&converter) {
+            let change = (next - lux).abs() / lux;
+            assert!(change < 0.02, "ch1 {}: {}", ch_1, change);
+        }
+
+        let integer = converter
+            .nano_lux(IntegrationTime::_200MS, Gain::Low, 1000, 185)
+            .unwrap();
+        let float = converter
+            .lux(IntegrationTime::_200MS, Gain::Low, 1000, 185)
+            .unwrap();
+        assert!((integer as f32 / 1e9 - float).abs() <= float * 1e-4);
+    }
+
+    #[test]
+    fn classifies_by_nearest_profile() {
+        let classifier = LightSourceClassifier::DEFAULT;
+        for (&source, &ratio) in LightSource::ALL.iter().zip(classifier.ir_ratios.iter()) {
+            let ch_1 = (1000.0 * ratio) as u16;
+            let classification = classifier.classify(1000, ch_1).unwrap();
+            assert_eq!(classification.source, source);
+            assert!(classification.confidence > 0.6, "{:?}", classification);
+            assert_eq!(classification.ir_ratio, ch_1 as f32 / 1000.0);
+        }
+
+        // Geometric midpoint of the fluorescent and sunlight profiles
+        let classification = classifier.classify(1000, 190).unwrap();
+        assert_eq!(classification.source, LightSource::Sunlight);
+        assert!((classification.confidence - 0.5).abs() < 0.05);
+
+        // Far outside the profiles, the nearest end is certain
+        let led = classifier.classify(1000, 0).unwrap();
+        assert_eq!(led.source, LightSource::Led);
+        assert!(led.confidence > 0.99);
+        let incandescent = classifier.classify(100, 5000).unwrap();
+        assert_eq!(incandescent.source, LightSource::Incandescent);
+        assert!(incandescent.confidence > 0.99);
+
+        assert_eq!(classifier.classify(49, 10), None);
+        assert!(classifier.classify(50, 10).is_some());
+    }
+
+    #[test]
+    fn weights_sum_to_one() {
+        let classifier = LightSourceClassifier::DEFAULT;
+        for &(ch_0, ch_1) in &[(1000, 0), (1000, 190), (1000, 600), (0, 0), (1, 65535)] {
+            let weights = classifier.weights(ch_0, ch_1);
+            let total: f32 = weights.iter().sum();
+            assert!((total - 1.0).abs() < 1e-5, "{:?}", weights);
+            assert!(weights.iter().all(|w| w.is_finite() && *w >= 0.0));
+        }
+        // Below min_counts the weights are still given
+        let weights = classifier.weights(10, 3);
+        assert_eq!(weights, classifier.weights(1000, 300));
+    }
+}
@@ -1,19 +1,38 @@
 use crate::{Gain, IntegrationTime};
+use core::convert::TryFrom;
 
 const INTEGER_CONVERSION_FACTOR: i64 = 1_000_000_000;
-const OVERFLOW_100MS: u16 = 36863;
-const OVERFLOW_OTHER: u16 = 65535;
 
 pub fn check_overflow(integration_time: IntegrationTime, ch_0: u16, ch_1: u16) -> bool {
-    let overflow_value = if let IntegrationTime::_100MS = integration_time {
-        OVERFLOW_100MS
-    } else {
-        OVERFLOW_OTHER
-    };
+    let overflow_value = integration_time.max_count();
 
     (ch_0 >= overflow_value) || (ch_1 >= overflow_value)
 }
 
+/// Largest CH1/CH0 assumed once CH1 has saturated; about that of incandescent
+/// light, the most infrared-heavy common source.
+pub(crate) const MAX_SATURATED_IR_RATIO: f32 = 0.6;
+
+/// Channel counts giving a lower bound on the lux of an overflowed reading.
+///
+/// The true CH0 is at least the largest count that doesn't overflow, and lux
+/// grows with CH0. A saturated CH1 only says that the infrared is strong, so
+/// it is replaced by [`MAX_SATURATED_IR_RATIO`] times CH0.
+pub(crate) fn saturation_bound_counts(
+    integration_time: IntegrationTime,
+    ch_0: u16,
+    ch_1: u16,
+) -> (u16, u16) {
+    let limit = integration_time.max_count() - 1;
+    let ch_0 = ch_0.min(limit);
+    let ch_1 = if ch_1 > limit {
+        (ch_0 as f32 * MAX_SATURATED_IR_RATIO) as u16
+    } else {
+        ch_1
+    };
+    (ch_0, ch_1)
+}
+
 /// Calculate lux from raw channel data.
 ///
 /// Exists because there's a few different ways to do this,
@@ -37,6 +56,102 @@ pub trait LuxConverter {
     }
 }
 
+/// Instance-based counterpart of [`LuxConverter`].
+///
+/// Object safe, so a converter can carry its own coefficients and be chosen
+/// at runtime. Every [`LuxConverter`] implements it.
+pub trait DynLuxConverter {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64>;
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        self.nano_lux(integration_time, gain, ch_0, ch_1)
+            .map(|lux| lux as f32 / 1_000_000_000.0)
+    }
+}
+
+impl<T: LuxConverter> DynLuxConverter for T {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64> {
+        T::calculate_nano_lux(integration_time, gain, ch_0, ch_1)
+    }
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        T::calculate_lux(integration_time, gain, ch_0, ch_1)
+    }
+}
+
+/// Lux conversion algorithms that can be selected at runtime, e.g. from
+/// stored configuration.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LuxAlgorithm {
+    #[default]
+    AdafruitPython,
+    Yocto,
+    AmsSunlight,
+    Ams(AmsLuxConverter),
+}
+
+impl DynLuxConverter for LuxAlgorithm {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64> {
+        match self {
+            Self::AdafruitPython => {
+                AdafruitPythonLuxConverter.nano_lux(integration_time, gain, ch_0, ch_1)
+            }
+            Self::Yocto => YoctoLuxConverter.nano_lux(integration_time, gain, ch_0, ch_1),
+            Self::AmsSunlight => {
+                AmsLuxConverterSunlight.nano_lux(integration_time, gain, ch_0, ch_1)
+            }
+            Self::Ams(converter) => converter.nano_lux(integration_time, gain, ch_0, ch_1),
+        }
+    }
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        match self {
+            Self::AdafruitPython => {
+                AdafruitPythonLuxConverter.lux(integration_time, gain, ch_0, ch_1)
+            }
+            Self::Yocto => YoctoLuxConverter.lux(integration_time, gain, ch_0, ch_1),
+            Self::AmsSunlight => AmsLuxConverterSunlight.lux(integration_time, gain, ch_0, ch_1),
+            Self::Ams(converter) => converter.lux(integration_time, gain, ch_0, ch_1),
+        }
+    }
+}
+
 /// Lux conversions taken from the Adafruit Python library
 pub struct AdafruitPythonLuxConverter;
 impl AdafruitPythonLuxConverter {
@@ -132,9 +247,157 @@ impl LuxConverter for YoctoLuxConverter {
     }
 }
 
+/// Coefficients of the AMS lux equations (DN40 / AN000170).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LuxCoefficients {
+    /// Device factor (DF)
+    pub device_factor: f32,
+    /// Glass attenuation (GA): 1.0 in open air, larger behind tinted windows
+    /// or diffusers
+    pub glass_attenuation: f32,
+    /// IR coefficient of the first equation
+    pub coef_b: f32,
+    /// Visible coefficient of the second equation
+    pub coef_c: f32,
+    /// IR coefficient of the second equation
+    pub coef_d: f32,
+}
+
+impl LuxCoefficients {
+    /// Values used by the Adafruit libraries for the TSL2591 in open air.
+    pub const DN40: Self = LuxCoefficients {
+        device_factor: 408.0,
+        glass_attenuation: 1.0,
+        coef_b: 1.64,
+        coef_c: 0.59,
+        coef_d: 0.86,
+    };
+
+    /// Single-equation sunlight fit from AN000170.
+    pub const SUNLIGHT: Self = LuxCoefficients {
+        device_factor: 52.0,
+        glass_attenuation: 1.0,
+        coef_b: 1.85,
+        coef_c: 0.0,
+        coef_d: 0.0,
+    };
+}
+
+impl Default for LuxCoefficients {
+    fn default() -> Self {
+        Self::DN40
+    }
+}
+
+fn to_nano(value: f32) -> i64 {
+    let nano = value as f64 * INTEGER_CONVERSION_FACTOR as f64;
+    if nano < 0.0 {
+        (nano - 0.5) as i64
+    } else {
+        (nano + 0.5) as i64
+    }
+}
+
+/// AMS application-note lux converter with configurable coefficients.
+///
+/// ```text
+/// CPL  = (ATIME_ms * AGAIN) / (GA * DF)
+/// Lux1 = (C0 - B * C1) / CPL
+/// Lux2 = (C * C0 - D * C1) / CPL
+/// Lux  = max(Lux1, Lux2, 0)
+/// ```
+///
+/// The integer path uses the coefficients rounded to nano-units, so both paths
+/// agree to within `f32` precision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmsLuxConverter {
+    coefficients: LuxCoefficients,
+    df_ga_nano: i64,
+    coef_b_nano: i64,
+    coef_c_nano: i64,
+    coef_d_nano: i64,
+}
+
+impl AmsLuxConverter {
+    pub fn new(coefficients: LuxCoefficients) -> Self {
+        AmsLuxConverter {
+            coefficients,
+            df_ga_nano: to_nano(coefficients.device_factor * coefficients.glass_attenuation),
+            coef_b_nano: to_nano(coefficients.coef_b),
+            coef_c_nano: to_nano(coefficients.coef_c),
+            coef_d_nano: to_nano(coefficients.coef_d),
+        }
+    }
+
+    pub fn coefficients(&self) -> LuxCoefficients {
+        self.coefficients
+    }
+}
+
+impl Default for AmsLuxConverter {
+    fn default() -> Self {
+        Self::new(LuxCoefficients::DN40)
+    }
+}
+
+impl DynLuxConverter for AmsLuxConverter {
+    fn nano_lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<i64> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            // Signal an overflow
+            return None;
+        }
+
+        let a_time = integration_time.get_integration_time_millis() as i128;
+        let a_gain = gain.get_multiplier() as i128;
+        let ch_0 = ch_0 as i128;
+        let ch_1 = ch_1 as i128;
+
+        // Counts scaled by 1e9
+        let counts1 = ch_0 * INTEGER_CONVERSION_FACTOR as i128 - self.coef_b_nano as i128 * ch_1;
+        let counts2 = self.coef_c_nano as i128 * ch_0 - self.coef_d_nano as i128 * ch_1;
+        let counts = i128::max(i128::max(counts1, counts2), 0);
+
+        let nano_lux = counts * self.df_ga_nano as i128
+            / (a_time * a_gain * INTEGER_CONVERSION_FACTOR as i128);
+        i64::try_from(nano_lux).ok()
+    }
+
+    fn lux(
+        &self,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            // Signal an overflow
+            return None;
+        }
+
+        let c = &self.coefficients;
+        let a_time = integration_time.get_integration_time_millis() as f32;
+        let a_gain = gain.get_multiplier() as f32;
+        let ch_0 = ch_0 as f32;
+        let ch_1 = ch_1 as f32;
+
+        let cpl = (a_time * a_gain) / (c.glass_attenuation * c.device_factor);
+        let lux1 = (ch_0 - c.coef_b * ch_1) / cpl;
+        let lux2 = (c.coef_c * ch_0 - c.coef_d * ch_1) / cpl;
+
+        Some(f32::max(f32::max(lux1, lux2), 0.0))
+    }
+}
+
 /// Based on https://ams.com/documents/20143/36005/AmbientLightSensors_AN000170_2-00.pdf
 ///
-/// Assumes sunlight spectrum
+/// Assumes sunlight spectrum; equivalent to an [`AmsLuxConverter`] with
+/// [`LuxCoefficients::SUNLIGHT`].
 pub struct AmsLuxConverterSunlight;
 impl LuxConverter for AmsLuxConverterSunlight {
     fn calculate_nano_lux(
@@ -143,11 +406,109 @@ impl LuxConverter for AmsLuxConverterSunlight {
         ch_0: u16,
         ch_1: u16,
     ) -> Option<i64> {
-        ((ch_0 as i64 * INTEGER_CONVERSION_FACTOR)
-            - 1_850_000_000 * (ch_1 as i64 * INTEGER_CONVERSION_FACTOR))
-            .checked_div(
-                (integration_time.get_integration_time_millis() * gain.get_multiplier() / 52)
-                    as i64,
-            )
+        AmsLuxConverter::new(LuxCoefficients::SUNLIGHT).nano_lux(integration_time, gain, ch_0, ch_1)
+    }
+
+    fn calculate_lux(
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<f32> {
+        AmsLuxConverter::new(LuxCoefficients::SUNLIGHT).lux(integration_time, gain, ch_0, ch_1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_paths_agree(converter: &AmsLuxConverter) {
+        for &time in &IntegrationTime::ALL {
+            for &gain in &Gain::ALL {
+                for ch_0 in (0..time.max_count()).step_by(997) {
+                    for &ir_percent in &[0u32, 10, 30, 50, 80, 100] {
+                        let ch_1 = (ch_0 as u32 * ir_percent / 100) as u16;
+                        let float = converter.lux(time, gain, ch_0, ch_1).unwrap();
+                        let nano = converter.nano_lux(time, gain, ch_0, ch_1).unwrap();
+                        let integer = nano as f32 / 1_000_000_000.0;
+                        let tolerance = 1e-4 * float.abs() + 1e-6;
+                        assert!(
+                            (integer - float).abs() <= tolerance,
+                            "{:?} {:?} {} {}: {} vs {}",
+                            time,
+                            gain,
+                            ch_0,
+                            ch_1,
+                            integer,
+                            float
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ams_integer_and_float_paths_agree() {
+        assert_paths_agree(&AmsLuxConverter::new(LuxCoefficients::DN40));
+        assert_paths_agree(&AmsLuxConverter::new(LuxCoefficients::SUNLIGHT));
+        assert_paths_agree(&AmsLuxConverter::new(LuxCoefficients {
+            glass_attenuation: 2.7,
+            ..LuxCoefficients::DN40
+        }));
+    }
+
+    #[test]
+    fn ams_sunlight_integer_scaling() {
+        // (1000 - 1.85 * 100) * 52 / (100 ms * 1x)
+        let nano = AmsLuxConverterSunlight::calculate_nano_lux(
+            IntegrationTime::_100MS,
+            Gain::Low,
+            1000,
+            100,
+        )
+        .unwrap();
+        // The coefficients are f32, so allow for their rounding
+        assert!((nano - 423_800_000_000).abs() < 10_000, "{}", nano);
+        let lux =
+            AmsLuxConverterSunlight::calculate_lux(IntegrationTime::_100MS, Gain::Low, 1000, 100)
+                .unwrap();
+        assert!((lux - 423.8).abs() < 1e-3);
+
+        // Overflow is still reported
+        assert_eq!(
+            AmsLuxConverterSunlight::calculate_nano_lux(
+                IntegrationTime::_100MS,
+                Gain::Low,
+                IntegrationTime::_100MS.max_count(),
+                0
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn saturation_bound_with_both_channels_saturated() {
+        let time = IntegrationTime::_100MS;
+        let limit = time.max_count() - 1;
+        let full = time.max_count();
+        assert_eq!(saturation_bound_counts(time, full, 1000), (limit, 1000));
+        assert_eq!(
+            saturation_bound_counts(time, full, full),
+            (limit, (limit as f32 * MAX_SATURATED_IR_RATIO) as u16)
+        );
+
+        // Treating a saturated CH1 as just below overflow would give zero.
+        let dn40 = AmsLuxConverter::new(LuxCoefficients::DN40);
+        assert_eq!(dn40.lux(time, Gain::Low, limit, limit), Some(0.0));
+        let (ch_0, ch_1) = saturation_bound_counts(time, full, full);
+        let bound = dn40.lux(time, Gain::Low, ch_0, ch_1).unwrap();
+        // (0.59 - 0.86 * 0.6) * 36862 * 408 / 100
+        assert!((bound - 11_130.0).abs() < 10.0, "{}", bound);
+
+        // The sunlight fit has no positive bound at that ratio.
+        let sunlight = AmsLuxConverter::new(LuxCoefficients::SUNLIGHT);
+        assert_eq!(sunlight.lux(time, Gain::Low, ch_0, ch_1), Some(0.0));
     }
 }
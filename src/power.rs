@@ -0,0 +1,93 @@
+//! Estimate the TSL2591's current draw and energy use for a measurement
+//! schedule, from the typical figures in the datasheet.
+//!
+//! This is plain arithmetic and does not touch the bus, so it can be used on
+//! the host at design time to size batteries and compare strategies.
+
+use crate::{sensor_impl::AVALID_POLL_MS, types::IntegrationTime};
+
+/// Typical supply current with the oscillator and ADC running (PON = AEN = 1).
+pub const ACTIVE_CURRENT_UA: f32 = 275.0;
+/// Typical supply current in the sleep state (PON = 0).
+pub const SLEEP_CURRENT_UA: f32 = 2.3;
+/// Typical supply voltage the currents are specified at.
+pub const SUPPLY_VOLTAGE: f32 = 3.0;
+
+/// How the sensor is operated between samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Power on for one integration per sample and sleep in between, as done
+    /// by [`Tsl2591::measure_once`](crate::Tsl2591::measure_once).
+    OneShot,
+    /// The ALS is left enabled and read once per sample period.
+    Continuous,
+}
+
+/// A measurement schedule to estimate the power budget of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub sample_period_ms: u32,
+    pub integration_time: IntegrationTime,
+    pub strategy: Strategy,
+    /// Threshold interrupts need the ALS to run continuously, so enabling
+    /// them forces [`Strategy::Continuous`] in the estimate.
+    pub interrupts: bool,
+}
+
+/// Current figures used for the estimate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerModel {
+    pub active_current_ua: f32,
+    pub sleep_current_ua: f32,
+    pub supply_voltage: f32,
+}
+
+impl Default for PowerModel {
+    fn default() -> Self {
+        PowerModel {
+            active_current_ua: ACTIVE_CURRENT_UA,
+            sleep_current_ua: SLEEP_CURRENT_UA,
+            supply_voltage: SUPPLY_VOLTAGE,
+        }
+    }
+}
+
+/// Result of [`PowerModel::estimate`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerEstimate {
+    /// Time per sample period the sensor spends powered on
+    pub active_ms_per_sample: u32,
+    pub average_current_ua: f32,
+    pub average_power_uw: f32,
+    pub energy_per_sample_uj: f32,
+}
+
+impl PowerEstimate {
+    /// Hours the sensor alone would run from a battery of the given capacity.
+    pub fn battery_life_hours(&self, capacity_mah: f32) -> f32 {
+        capacity_mah * 1000.0 / self.average_current_ua
+    }
+}
+
+impl PowerModel {
+    pub fn estimate(&self, schedule: &Schedule) -> PowerEstimate {
+        let continuous = schedule.interrupts || schedule.strategy == Strategy::Continuous;
+        // A one-shot measurement waits one integration cycle and then polls
+        // AVALID, so budget one extra poll interval.
+        let one_shot_ms = schedule.integration_time.get_integration_time_millis() + AVALID_POLL_MS;
+        let period_ms = schedule.sample_period_ms.max(one_shot_ms);
+        let active_ms = if continuous { period_ms } else { one_shot_ms };
+
+        let charge = self.active_current_ua * active_ms as f32
+            + self.sleep_current_ua * (period_ms - active_ms) as f32;
+        let average_current_ua = charge / period_ms as f32;
+        let average_power_uw = average_current_ua * self.supply_voltage;
+
+        PowerEstimate {
+            active_ms_per_sample: active_ms,
+            average_current_ua,
+            average_power_uw,
+            energy_per_sample_uj: average_power_uw * period_ms as f32 / 1000.0,
+        }
+    }
+}
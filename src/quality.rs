@@ -0,0 +1,330 @@
+//! Uncertainty estimates and quality flags for lux values.
+
+use crate::{
+    calibration::gain_index,
+    lux_conversion::{check_overflow, DynLuxConverter},
+    types::{Gain, IntegrationTime},
+};
+
+/// Conditions that make a lux value less trustworthy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QualityFlags {
+    /// CH0 is below [`QualityModel::low_signal_counts`]
+    pub low_signal: bool,
+    /// A channel is above [`QualityModel::near_saturation`] of full scale
+    pub near_saturation: bool,
+    /// CH1/CH0 is above [`QualityModel::ir_dominated_ratio`]
+    pub ir_dominated: bool,
+    /// The converter produced a non-positive value that was reported as zero
+    pub clamped_to_zero: bool,
+}
+
+impl QualityFlags {
+    /// No flag is set.
+    pub fn is_clean(&self) -> bool {
+        *self == QualityFlags::default()
+    }
+}
+
+/// A lux value with its estimated one-sigma uncertainty.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LuxQuality {
+    pub lux: f32,
+    /// Absolute one-sigma uncertainty in lux
+    pub uncertainty: f32,
+    pub flags: QualityFlags,
+}
+
+impl LuxQuality {
+    /// Uncertainty relative to the value, `None` for zero lux.
+    pub fn relative_uncertainty(&self) -> Option<f32> {
+        if self.lux > 0.0 {
+            Some(self.uncertainty / self.lux)
+        } else {
+            None
+        }
+    }
+
+    /// Scale value and uncertainty, e.g. by a gain calibration correction.
+    pub fn scaled(self, factor: f32) -> Self {
+        LuxQuality {
+            lux: self.lux * factor,
+            uncertainty: self.uncertainty * factor,
+            ..self
+        }
+    }
+}
+
+/// Error model and flag thresholds used to assess a reading.
+///
+/// Count noise is shot noise plus a constant read noise, propagated through
+/// the converter numerically, so it applies to any [`DynLuxConverter`].
+/// Systematic terms are relative: a converter term growing with the CH1/CH0
+/// ratio, and a per-gain term for the spread of the gain multipliers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityModel {
+    /// Read noise in counts, added in quadrature to shot noise
+    pub read_noise_counts: f32,
+    /// Relative converter uncertainty for pure visible light
+    pub converter_uncertainty: f32,
+    /// Additional relative converter uncertainty per unit of CH1/CH0
+    pub ir_uncertainty: f32,
+    /// Relative uncertainty of the gain multiplier, indexed Low, Med, High, Max.
+    /// Set to zero after a gain calibration.
+    pub gain_uncertainty: [f32; 4],
+    pub low_signal_counts: u16,
+    /// Fraction of the overflow count above which a channel is near saturation
+    pub near_saturation: f32,
+    pub ir_dominated_ratio: f32,
+}
+
+impl QualityModel {
+    pub const DEFAULT: Self = QualityModel {
+        read_noise_counts: 1.0,
+        converter_uncertainty: 0.05,
+        ir_uncertainty: 0.3,
+        gain_uncertainty: [0.0, 0.05, 0.05, 0.05],
+        low_signal_counts: 100,
+        near_saturation: 0.9,
+        ir_dominated_ratio: 0.6,
+    };
+
+    /// Assess raw channel data converted with `converter`. Returns `None` if
+    /// the data overflowed or the converter produced no value.
+    pub fn assess<C: DynLuxConverter + ?Sized>(
+        &self,
+        converter: &C,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Option<LuxQuality> {
+        if check_overflow(integration_time, ch_0, ch_1) {
+            return None;
+        }
+        let raw = converter.lux(integration_time, gain, ch_0, ch_1)?;
+        let limit = integration_time.max_count() - 1;
+
+        let ratio = if ch_0 > 0 {
+            ch_1 as f32 / ch_0 as f32
+        } else {
+            0.0
+        };
+        let flags = QualityFlags {
+            low_signal: ch_0 < self.low_signal_counts,
+            near_saturation: ch_0.max(ch_1) as f32 >= self.near_saturation * limit as f32,
+            ir_dominated: ratio > self.ir_dominated_ratio,
+            clamped_to_zero: raw <= 0.0 && ch_0 > 0,
+        };
+
+        let lux_at = |ch_0: u16, ch_1: u16| converter.lux(integration_time, gain, ch_0, ch_1);
+        let sigma_0 = self.count_sigma(ch_0);
+        let sigma_1 = self.count_sigma(ch_1);
+        let d_0 = propagate(raw, ch_0, sigma_0, limit, |ch| lux_at(ch, ch_1));
+        let d_1 = propagate(raw, ch_1, sigma_1, limit, |ch| lux_at(ch_0, ch));
+
+        let lux = raw.max(0.0);
+        let systematic = self.converter_uncertainty
+            + self.ir_uncertainty * ratio
+            + self.gain_uncertainty[gain_index(gain)];
+        let systematic = lux * systematic;
+        let uncertainty = libm::sqrtf(d_0 * d_0 + d_1 * d_1 + systematic * systematic);
+
+        Some(LuxQuality {
+            lux,
+            uncertainty,
+            flags,
+        })
+    }
+
+    fn count_sigma(&self, count: u16) -> f32 {
+        libm::sqrtf(count as f32 + self.read_noise_counts * self.read_noise_counts)
+    }
+}
+
+impl Default for QualityModel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Change in lux for a one-sigma change of one channel, by finite differences
+/// over `count ± sigma` clamped to the valid range.
+fn propagate<F: Fn(u16) -> Option<f32>>(
+    center: f32,
+    count: u16,
+    sigma: f32,
+    limit: u16,
+    lux_at: F,
+) -> f32 {
+    let step = libm::ceilf(sigma) as u16;
+    let low = count.saturating_sub(step);
+    let high = count.saturating_add(step).min(limit);
+    let (low, low_lux) = match lux_at(low) {
+        Some(lux) => (low, lux),
+        None => (count, center),
+    };
+    let (high, high_lux) = match lux_at(high) {
+        Some(lux) => (high, lux),
+        None => (count, center),
+    };
+    if high == low {
+        return 0.0;
+    }
+    (high_lux - low_lux) / (high - low) as f32 * sigma
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lux_conversion::{
+        AdafruitPythonLuxConverter, AmsLuxConverter, LuxCoefficients, YoctoLuxConverter,
+    };
+
+    const TIME: IntegrationTime = IntegrationTime::_200MS;
+
+    fn dn40() -> AmsLuxConverter {
+        AmsLuxConverter::new(LuxCoefficients::DN40)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected.abs() * 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn uncertainty_combines_noise_and_systematic_terms() {
+        let model = QualityModel::DEFAULT;
+        let quality = model
+            .assess(&dn40(), TIME, Gain::Low, 10_000, 1000)
+            .unwrap();
+        assert!(quality.flags.is_clean());
+
+        // First DN40 equation: lux = (CH0 - 1.64 CH1) * 408 / 200
+        let cpl = 200.0 / 408.0;
+        assert_close(quality.lux, (10_000.0 - 1640.0) / cpl);
+        let d_0 = libm::sqrtf(10_001.0) / cpl;
+        let d_1 = 1.64 * libm::sqrtf(1001.0) / cpl;
+        let systematic = quality.lux * (0.05 + 0.3 * 0.1);
+        let expected = libm::sqrtf(d_0 * d_0 + d_1 * d_1 + systematic * systematic);
+        assert_close(quality.uncertainty, expected);
+        assert_close(
+            quality.relative_uncertainty().unwrap(),
+            expected / quality.lux,
+        );
+
+        // The gain term only applies above Low
+        let med = model
+            .assess(&dn40(), TIME, Gain::Med, 10_000, 1000)
+            .unwrap();
+        let systematic = med.lux * (0.05 + 0.3 * 0.1 + 0.05);
+        assert!(med.uncertainty > systematic);
+        assert!(med.uncertainty < systematic * 1.01);
+
+        let scaled = quality.scaled(2.0);
+        assert_close(scaled.lux, 2.0 * quality.lux);
+        assert_close(scaled.uncertainty, 2.0 * quality.uncertainty);
+        assert_eq!(scaled.flags, quality.flags);
+    }
+
+    #[test]
+    fn shot_noise_dominates_weak_signals() {
+        let model = QualityModel {
+            converter_uncertainty: 0.0,
+            ir_uncertainty: 0.0,
+            ..QualityModel::DEFAULT
+        };
+        let quality = model.assess(&dn40(), TIME, Gain::Low, 400, 0).unwrap();
+        // sqrt(400 + 1) counts on CH0, and CH1 still carries the read noise
+        let counts = libm::sqrtf(401.0 + 1.64 * 1.64);
+        assert_close(quality.relative_uncertainty().unwrap(), counts / 400.0);
+    }
+
+    #[test]
+    fn flags_follow_thresholds() {
+        let model = QualityModel::DEFAULT;
+        let flags = |time, ch_0, ch_1| {
+            model
+                .assess(&dn40(), time, Gain::Low, ch_0, ch_1)
+                .unwrap()
+                .flags
+        };
+
+        assert!(flags(TIME, 99, 0).low_signal);
+        assert!(!flags(TIME, 100, 0).low_signal);
+
+        // 90% of the 36862 counts available at 100 ms is 33175.8
+        let short = IntegrationTime::_100MS;
+        assert!(!flags(short, 33_175, 0).near_saturation);
+        assert!(flags(short, 33_176, 0).near_saturation);
+        assert!(flags(short, 33_176, 33_176).near_saturation);
+        assert!(!flags(TIME, 33_176, 0).near_saturation);
+
+        assert!(!flags(TIME, 1000, 600).ir_dominated);
+        assert!(flags(TIME, 1000, 601).ir_dominated);
+    }
+
+    #[test]
+    fn clamped_to_zero_with_and_without_internal_clamping() {
+        let model = QualityModel::DEFAULT;
+        // Both DN40 equations are negative at CH1/CH0 = 0.9.
+        let unclamped = AdafruitPythonLuxConverter
+            .lux(TIME, Gain::Low, 1000, 900)
+            .unwrap();
+        assert!(unclamped < 0.0);
+        assert_eq!(dn40().lux(TIME, Gain::Low, 1000, 900), Some(0.0));
+
+        let converters: [&dyn DynLuxConverter; 2] = [&AdafruitPythonLuxConverter, &dn40()];
+        for converter in converters {
+            let quality = model.assess(converter, TIME, Gain::Low, 1000, 900).unwrap();
+            assert_eq!(quality.lux, 0.0);
+            assert!(quality.flags.clamped_to_zero);
+            assert!(quality.flags.ir_dominated);
+            assert!(quality.uncertainty.is_finite() && quality.uncertainty >= 0.0);
+            assert_eq!(quality.relative_uncertainty(), None);
+
+            // Darkness is zero without anything being clamped
+            let dark = model.assess(converter, TIME, Gain::Low, 0, 0).unwrap();
+            assert_eq!(dark.lux, 0.0);
+            assert!(!dark.flags.clamped_to_zero);
+            assert!(dark.flags.low_signal);
+        }
+    }
+
+    #[test]
+    fn no_assessment_without_a_value() {
+        let model = QualityModel::DEFAULT;
+        assert_eq!(
+            model.assess(&dn40(), IntegrationTime::_100MS, Gain::Low, 36_863, 0),
+            None
+        );
+        // Yocto rejects readings outside its range per gain
+        assert_eq!(
+            model.assess(&YoctoLuxConverter, TIME, Gain::Med, 2000, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn propagation_stays_inside_the_valid_range() {
+        let line = |ch: u16| Some(2.0 * ch as f32);
+        assert_close(propagate(200.0, 100, 10.0, 1000, line), 20.0);
+        // Clamped at the top of the range and at zero, the slope is kept.
+        assert_close(propagate(1980.0, 990, 30.0, 1000, line), 60.0);
+        assert_close(propagate(10.0, 5, 30.0, 1000, line), 60.0);
+        // A converter rejecting the shifted counts leaves the centre value.
+        let picky = |ch: u16| {
+            if ch <= 100 {
+                Some(2.0 * ch as f32)
+            } else {
+                None
+            }
+        };
+        assert_close(propagate(200.0, 100, 10.0, 1000, picky), 20.0);
+        assert_eq!(propagate(200.0, 100, 10.0, 1000, |_| None), 0.0);
+    }
+}
@@ -1,8 +1,20 @@
-use crate::lux_conversion::LuxConverter;
+use crate::lux_conversion::{
+    check_overflow, saturation_bound_counts, DynLuxConverter, LuxAlgorithm, LuxConverter,
+};
 use crate::{
+    calibration::{DarkOffsets, GainCalibration},
     chip,
+    config::Config,
+    diagnostics::{RegisterSnapshot, SelfTestReport},
     error::Error,
-    types::{Enable, Gain, IntegrationTime, Mode, Status},
+    irradiance::{Irradiance, SpectralModel},
+    quality::{LuxQuality, QualityModel},
+    sqm::{SkyQuality, SqmConfig},
+    state::{Measure, Off, On, PowerState, Untracked},
+    types::{
+        Control, DeviceInfo, Enable, Gain, IntegrationTime, LuxEstimate, Measurement, Mode,
+        Persistence, Status,
+    },
 };
 use core::marker::PhantomData;
 #[cfg(feature = "blocking")]
@@ -16,13 +28,67 @@ use embedded_hal_async::{
     i2c::{I2c, SevenBitAddress},
 };
 
-pub struct Tsl2591<I, D> {
+/// TSL2591 driver.
+///
+/// `S` optionally tracks the sensor's power state at the type level. The
+/// default, [`Untracked`], exposes every method; [`into_off`](Self::into_off)
+/// switches to the [`Off`]/[`On`] typestate API, where measurement methods only
+/// exist while the sensor is powered on.
+pub struct Tsl2591<I, D, S = Untracked> {
     i2c: I,
     integration_time: IntegrationTime,
     gain: Gain,
+    enable: Enable,
+    /// ENABLE bits other than PON and AEN that powering on sets
+    enable_flags: Enable,
+    thresholds: (u16, u16),
+    no_persist_thresholds: (u16, u16),
+    persistence: Persistence,
+    device_info: DeviceInfo,
+    lux_algorithm: LuxAlgorithm,
+    gain_calibration: GainCalibration,
+    dark_offsets: DarkOffsets,
     delay: PhantomData<D>,
+    state: PhantomData<S>,
+}
+
+impl<I, D, S> Tsl2591<I, D, S> {
+    fn into_state<T>(self) -> Tsl2591<I, D, T> {
+        Tsl2591 {
+            i2c: self.i2c,
+            integration_time: self.integration_time,
+            gain: self.gain,
+            enable: self.enable,
+            enable_flags: self.enable_flags,
+            thresholds: self.thresholds,
+            no_persist_thresholds: self.no_persist_thresholds,
+            persistence: self.persistence,
+            device_info: self.device_info,
+            lux_algorithm: self.lux_algorithm,
+            gain_calibration: self.gain_calibration,
+            dark_offsets: self.dark_offsets,
+            delay: PhantomData,
+            state: PhantomData,
+        }
+    }
+
+    /// Stop tracking the power state in the type system.
+    pub fn into_untracked(self) -> Tsl2591<I, D> {
+        self.into_state()
+    }
 }
 
+/// Mask of the CONTROL bits that read back (AGAIN and ATIME).
+const CONTROL_READBACK_MASK: u8 = 0x37;
+/// Pattern written to the ALS threshold registers during the self-test.
+const SELF_TEST_THRESHOLDS: (u16, u16) = (0xA55A, 0x5AA5);
+/// Readings averaged per gain by `calibrate_gain`.
+const GAIN_CALIBRATION_READINGS: usize = 4;
+/// Readings averaged per gain and integration time by `calibrate_dark`.
+const DARK_CALIBRATION_READINGS: u32 = 2;
+/// Interval at which STATUS is polled while waiting for AVALID.
+pub(crate) const AVALID_POLL_MS: u32 = 10;
+
 #[cfg(feature = "blocking")]
 impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay>
 where
@@ -30,17 +96,7 @@ where
     Delay: DelayNs,
 {
     pub fn new(i2c: I2C) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
-        let mut driver = Tsl2591 {
-            i2c,
-            integration_time: IntegrationTime::_200MS,
-            gain: Gain::Low,
-            delay: PhantomData,
-        };
-        let id = driver.get_id()?;
-        if id != chip::ID {
-            return Err(Error::IdMismatch(id));
-        }
-        Ok(driver)
+        Self::new_with_allowed_ids(i2c, IntegrationTime::_200MS, Gain::Low, &[chip::ID])
     }
 
     pub fn new_define_integration(
@@ -48,19 +104,165 @@ where
         integration_time: IntegrationTime,
         gain: Gain,
     ) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
+        Self::new_with_allowed_ids(i2c, integration_time, gain, &[chip::ID])
+    }
+
+    /// Like [`new_define_integration`](Self::new_define_integration), but
+    /// accepts any part whose ID register is in `allowed_ids` instead of only
+    /// the TSL2591 ID. Useful for pin-compatible variants.
+    pub fn new_with_allowed_ids(
+        i2c: I2C,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        allowed_ids: &[u8],
+    ) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
+        // Powering on enables both interrupts until a Config says otherwise.
+        let mut enable_flags = Enable::default();
+        enable_flags.set_AIEN(true);
+        enable_flags.set_NPIEN(true);
         let mut driver = Tsl2591 {
             i2c,
             integration_time,
             gain,
+            enable: Enable::default(),
+            enable_flags,
+            thresholds: (0, 0),
+            no_persist_thresholds: (0, 0),
+            persistence: Persistence::Every,
+            device_info: DeviceInfo::default(),
+            lux_algorithm: LuxAlgorithm::default(),
+            gain_calibration: GainCalibration::default(),
+            dark_offsets: DarkOffsets::default(),
             delay: PhantomData,
+            state: PhantomData,
         };
-        let id = driver.get_id()?;
-        if id != chip::ID {
-            return Err(Error::IdMismatch(id));
+        let device_info = driver.get_device_info()?;
+        if !allowed_ids.contains(&device_info.id) {
+            return Err(Error::IdMismatch(device_info));
         }
+        driver.device_info = device_info;
+        // The part powers up with its own CONTROL defaults; write the cached
+        // gain and integration time so the two agree from the start.
+        driver.write_register(chip::CONTROL, driver.control().0)?;
         Ok(driver)
     }
 
+    pub fn disable(&mut self) -> Result<(), Error<I2cError>> {
+        self.power_off()
+    }
+
+    /// Power the sensor on and enable the ALS, with the interrupt and
+    /// sleep-after-interrupt flags of the last applied [`Config`] (AIEN and
+    /// NPIEN until one is applied).
+    pub fn enable(&mut self) -> Result<(), Error<I2cError>> {
+        self.power_on()
+    }
+
+    /// Write a complete configuration to the sensor.
+    ///
+    /// The ALS is held disabled while CONTROL, the thresholds and the
+    /// persistence filter are written, and ENABLE is written last, so the
+    /// sensor never integrates with a partial configuration. If a write fails,
+    /// the configuration is still cached and can be retried with
+    /// [`restore_configuration`](Self::restore_configuration).
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::ENABLE, Enable::default().0)?;
+        self.integration_time = config.integration_time;
+        self.gain = config.gain;
+        self.enable = config.enable;
+        self.enable_flags = config.enable;
+        self.enable_flags.set_PON(false);
+        self.enable_flags.set_AEN(false);
+        self.thresholds = config.thresholds;
+        self.no_persist_thresholds = config.no_persist_thresholds;
+        self.persistence = config.persistence;
+        self.restore_configuration()
+    }
+
+    /// Power the sensor off and track its power state in the type system from
+    /// here on.
+    pub fn into_off(mut self) -> Result<Tsl2591<I2C, Delay, Off>, Error<I2cError>> {
+        self.power_off()?;
+        Ok(self.into_state())
+    }
+
+    /// Take a single measurement with minimal on-time.
+    ///
+    /// Powers the sensor on with the cached gain and integration time, waits
+    /// for exactly one valid integration cycle, reads both channels and powers
+    /// the sensor off again.
+    pub fn measure_once(&mut self, delay: &mut Delay) -> Result<Measurement, Error<I2cError>> {
+        self.measure_once_inner(delay)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<I2C, I2cError, Delay, S> Tsl2591<I2C, Delay, S>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+    S: PowerState,
+{
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    pub fn integration_time(&self) -> IntegrationTime {
+        self.integration_time
+    }
+
+    /// Select the algorithm used by [`lux`](Self::lux) and
+    /// [`nano_lux`](Self::nano_lux).
+    pub fn set_lux_algorithm(&mut self, lux_algorithm: LuxAlgorithm) {
+        self.lux_algorithm = lux_algorithm;
+    }
+
+    pub fn lux_algorithm(&self) -> LuxAlgorithm {
+        self.lux_algorithm
+    }
+
+    /// Use measured gain multipliers for every lux calculation.
+    pub fn set_gain_calibration(&mut self, gain_calibration: GainCalibration) {
+        self.gain_calibration = gain_calibration;
+    }
+
+    pub fn gain_calibration(&self) -> GainCalibration {
+        self.gain_calibration
+    }
+
+    /// Subtract dark offsets from the channel data before every lux
+    /// calculation.
+    pub fn set_dark_offsets(&mut self, dark_offsets: DarkOffsets) {
+        self.dark_offsets = dark_offsets;
+    }
+
+    pub fn dark_offsets(&self) -> DarkOffsets {
+        self.dark_offsets
+    }
+
+    fn dark_compensate(&self, ch_0: u16, ch_1: u16) -> Result<(u16, u16), Error<I2cError>> {
+        if check_overflow(self.integration_time, ch_0, ch_1) {
+            return Err(Error::SignalOverflow);
+        }
+        Ok(self
+            .dark_offsets
+            .compensate(self.gain, self.integration_time, ch_0, ch_1))
+    }
+
+    /// The configuration the driver last wrote to the sensor. While the sensor
+    /// is powered off, the enable flags it will be powered on with are
+    /// reported without PON and AEN.
+    pub fn config(&self) -> Config {
+        Config {
+            gain: self.gain,
+            integration_time: self.integration_time,
+            enable: Enable(self.enable.0 | self.enable_flags.0),
+            thresholds: self.thresholds,
+            no_persist_thresholds: self.no_persist_thresholds,
+            persistence: self.persistence,
+        }
+    }
+
     fn get_id(&mut self) -> Result<u8, Error<I2cError>> {
         let mut buffer = [0u8; 1];
         self.i2c
@@ -68,25 +270,93 @@ where
         Ok(buffer[0])
     }
 
+    fn get_package_id(&mut self) -> Result<u8, Error<I2cError>> {
+        let mut buffer = [0u8; 1];
+        self.i2c.write_read(
+            chip::I2C,
+            &[chip::COMMAND_BIT | chip::PID_ADDR],
+            &mut buffer,
+        )?;
+        Ok(buffer[0])
+    }
+
+    /// Read the ID and package ID registers from the sensor.
+    pub fn get_device_info(&mut self) -> Result<DeviceInfo, Error<I2cError>> {
+        Ok(DeviceInfo {
+            id: self.get_id()?,
+            package_id: self.get_package_id()?,
+        })
+    }
+
+    /// The device info read when the driver was constructed.
+    pub fn device_info(&self) -> DeviceInfo {
+        self.device_info
+    }
+
+    fn control(&self) -> Control {
+        Control::new(self.integration_time, self.gain)
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, Error<I2cError>> {
+        let mut buffer = [0u8; 1];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | register], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error<I2cError>> {
+        self.i2c
+            .write(chip::I2C, &[chip::COMMAND_BIT | register, value])?;
+        Ok(())
+    }
+
+    fn write_threshold_pair(
+        &mut self,
+        register: u8,
+        (low, high): (u16, u16),
+    ) -> Result<(), Error<I2cError>> {
+        let [low_l, low_h] = low.to_le_bytes();
+        let [high_l, high_h] = high.to_le_bytes();
+        self.i2c.write(
+            chip::I2C,
+            &[chip::COMMAND_BIT | register, low_l, low_h, high_l, high_h],
+        )?;
+        Ok(())
+    }
+
+    fn read_threshold_pair(&mut self, register: u8) -> Result<(u16, u16), Error<I2cError>> {
+        let mut buffer = [0u8; 4];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | register], &mut buffer)?;
+        Ok((
+            u16::from_le_bytes([buffer[0], buffer[1]]),
+            u16::from_le_bytes([buffer[2], buffer[3]]),
+        ))
+    }
+
+    fn write_special(&mut self, function: u8) -> Result<(), Error<I2cError>> {
+        self.i2c
+            .write(chip::I2C, &[chip::SPECIAL_FUNCTION | function])?;
+        Ok(())
+    }
+
+    fn read_channels(&mut self) -> Result<(u16, u16), Error<I2cError>> {
+        let mut buffer = [0u8; 4];
+        self.i2c.write_read(
+            chip::I2C,
+            &[chip::COMMAND_BIT | chip::CHAN0_LOW],
+            &mut buffer,
+        )?;
+        let channel_0 = u16::from_le_bytes([buffer[0], buffer[1]]);
+        let channel_1 = u16::from_le_bytes([buffer[2], buffer[3]]);
+        Ok((channel_0, channel_1))
+    }
+
     pub fn set_gain(&mut self, gain: Option<Gain>) -> Result<(), Error<I2cError>> {
         if let Some(gain) = gain {
-            self.i2c.write(
-                0x29,
-                &[
-                    chip::COMMAND_BIT | chip::CONTROL,
-                    self.integration_time as u8 | gain as u8,
-                ],
-            )?;
-        } else {
-            self.i2c.write(
-                0x29,
-                &[
-                    chip::COMMAND_BIT | chip::CONTROL,
-                    self.integration_time as u8 | self.gain as u8,
-                ],
-            )?;
+            self.gain = gain;
         }
-        Ok(())
+        self.write_register(chip::CONTROL, self.control().0)
     }
 
     pub fn set_timing(
@@ -94,44 +364,79 @@ where
         integration_time: Option<IntegrationTime>,
     ) -> Result<(), Error<I2cError>> {
         if let Some(integration_time) = integration_time {
-            self.i2c.write(
-                0x29,
-                &[
-                    chip::COMMAND_BIT | chip::CONTROL,
-                    integration_time as u8 | self.gain as u8,
-                ],
-            )?;
-        } else {
-            self.i2c.write(
-                0x29,
-                &[
-                    chip::COMMAND_BIT | chip::CONTROL,
-                    self.integration_time as u8 | self.gain as u8,
-                ],
-            )?;
+            self.integration_time = integration_time;
         }
+        self.write_register(chip::CONTROL, self.control().0)
+    }
+
+    /// Set the low and high ALS thresholds used by the persistent interrupt.
+    pub fn set_thresholds(&mut self, low: u16, high: u16) -> Result<(), Error<I2cError>> {
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, (low, high))?;
+        self.thresholds = (low, high);
         Ok(())
     }
 
-    pub fn disable(&mut self) -> Result<(), Error<I2cError>> {
-        self.i2c.write(
-            0x29,
-            &[chip::COMMAND_BIT | chip::ENABLE, chip::ENABLE_POWEROFF],
-        )?;
+    /// Set the low and high ALS thresholds used by the no-persist interrupt.
+    pub fn set_no_persist_thresholds(
+        &mut self,
+        low: u16,
+        high: u16,
+    ) -> Result<(), Error<I2cError>> {
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_NPAILTL, (low, high))?;
+        self.no_persist_thresholds = (low, high);
         Ok(())
     }
 
-    pub fn enable(&mut self) -> Result<(), Error<I2cError>> {
-        self.i2c.write(
-            0x29,
-            &[
-                chip::COMMAND_BIT | chip::ENABLE,
-                chip::ENABLE_POWERON | chip::ENABLE_AEN | chip::ENABLE_AIEN | chip::ENABLE_NPIEN,
-            ],
-        )?;
+    pub fn set_persistence(&mut self, persistence: Persistence) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::TSL2591_PERSIST_FILTER, persistence as u8)?;
+        self.persistence = persistence;
+        Ok(())
+    }
+
+    fn power_off(&mut self) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::ENABLE, Enable::default().0)?;
+        self.enable = Enable::default();
+        Ok(())
+    }
+
+    fn power_on(&mut self) -> Result<(), Error<I2cError>> {
+        let mut enable = self.enable_flags;
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        self.write_register(chip::ENABLE, enable.0)?;
+        self.enable = enable;
         Ok(())
     }
 
+    /// Write the driver's cached configuration (gain, integration time,
+    /// thresholds, persistence and enable flags) back to the sensor.
+    pub fn restore_configuration(&mut self) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::CONTROL, self.control().0)?;
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, self.thresholds)?;
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_NPAILTL, self.no_persist_thresholds)?;
+        self.write_register(chip::TSL2591_PERSIST_FILTER, self.persistence as u8)?;
+        self.write_register(chip::ENABLE, self.enable.0)
+    }
+
+    /// Detect whether the sensor lost power since it was configured and, if
+    /// so, restore the configuration.
+    ///
+    /// A brown-out is assumed when ENABLE reads as powered off although the
+    /// driver powered the sensor on, or when CONTROL no longer matches the
+    /// cached gain and integration time. Returns `true` if a recovery happened.
+    pub fn check_brown_out(&mut self) -> Result<bool, Error<I2cError>> {
+        let enable = Enable(self.read_register(chip::ENABLE)?);
+        let control = Control(self.read_register(chip::CONTROL)?);
+
+        let lost_power = self.enable.PON() && !enable.PON();
+        let control_reset = control.0 & CONTROL_READBACK_MASK != self.control().0;
+        if lost_power || control_reset {
+            self.restore_configuration()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     pub fn get_enable(&mut self) -> Result<Enable, Error<I2cError>> {
         let mut status = [0u8; 1];
         self.i2c
@@ -139,6 +444,10 @@ where
         Ok(Enable(status[0]))
     }
 
+    pub fn get_control(&mut self) -> Result<Control, Error<I2cError>> {
+        Ok(Control(self.read_register(chip::CONTROL)?))
+    }
+
     pub fn get_status(&mut self) -> Result<Status, Error<I2cError>> {
         let mut status = [0u8; 1];
         self.i2c
@@ -146,192 +455,966 @@ where
         Ok(Status(status[0]))
     }
 
-    pub fn get_channel_data(&mut self, delay: &mut Delay) -> Result<(u16, u16), Error<I2cError>> {
-        delay.delay_ms(120);
-        let mut buffer_1 = [0u8; 2];
-        let mut buffer_2 = [0u8; 2];
-        self.i2c.write_read(
-            chip::I2C,
-            &[chip::COMMAND_BIT | chip::CHAN0_LOW],
-            &mut buffer_1,
-        )?;
-        self.i2c.write_read(
-            chip::I2C,
-            &[chip::COMMAND_BIT | chip::CHAN1_LOW],
-            &mut buffer_2,
-        )?;
-        let channel_0 = ((buffer_1[0] as u16) << 8) | buffer_1[1] as u16;
-        let channel_1 = ((buffer_2[0] as u16) << 8) | buffer_2[1] as u16;
-        Ok((channel_0, channel_1))
+    /// Burst-read every register from ENABLE (0x00) to C1DATAH (0x17).
+    pub fn get_register_snapshot(&mut self) -> Result<RegisterSnapshot, Error<I2cError>> {
+        let mut raw = [0u8; chip::REGISTER_COUNT];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | chip::ENABLE], &mut raw)?;
+        Ok(RegisterSnapshot::from_raw(raw))
     }
 
-    pub fn get_luminosity(
+    fn measure_once_inner(&mut self, delay: &mut Delay) -> Result<Measurement, Error<I2cError>> {
+        // Always try to power off, even after a failed transfer, so a bus
+        // glitch doesn't leave the ADC running; the first error wins.
+        let channels = self.integrate_once(delay);
+        let powered_off = self.power_off();
+        let (ch_0, ch_1) = channels?;
+        powered_off?;
+        Ok(Measurement {
+            ch_0,
+            ch_1,
+            integration_time: self.integration_time,
+            gain: self.gain,
+        })
+    }
+
+    fn integrate_once(&mut self, delay: &mut Delay) -> Result<(u16, u16), Error<I2cError>> {
+        // Stop a running ALS first, so AVALID and the channel data can't come
+        // from a cycle that started under the previous gain or time.
+        self.write_register(chip::ENABLE, Enable::default().0)?;
+        self.write_register(chip::CONTROL, self.control().0)?;
+        let mut enable = Enable::default();
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        self.write_register(chip::ENABLE, enable.0)?;
+
+        // The first cycle completes one integration time after AEN is set;
+        // poll for the remainder to absorb oscillator tolerance.
+        let integration_ms = self.integration_time.get_integration_time_millis();
+        delay.delay_ms(integration_ms);
+        let mut waited = 0;
+        loop {
+            if self.get_status()?.AVALID() {
+                return self.read_channels();
+            }
+            if waited >= integration_ms {
+                return Err(Error::Timeout);
+            }
+            delay.delay_ms(AVALID_POLL_MS);
+            waited += AVALID_POLL_MS;
+        }
+    }
+
+    /// Measure the gain steps the current light allows.
+    ///
+    /// Each step compares a gain with the next lower one, see
+    /// [`calibrate_gain_step`](Self::calibrate_gain_step). One light level
+    /// can't serve every step: Low to Med needs bright light, High to Max light
+    /// several hundred times dimmer. Steps the light doesn't allow are left
+    /// unmeasured, as reported by [`GainCalibration::steps`]; repeat under
+    /// other light and combine the results with [`GainCalibration::merge`]
+    /// before passing them to
+    /// [`set_gain_calibration`](Self::set_gain_calibration). The cached
+    /// configuration is restored afterwards.
+    pub fn calibrate_gain(
         &mut self,
-        mode: Mode,
         delay: &mut Delay,
-    ) -> Result<u16, Error<I2cError>> {
-        let (channel_0, channel_1) = self.get_channel_data(delay)?;
-        let full_luminosity: u32 = ((channel_1 as u32) << 16) | channel_0 as u32;
-
-        match mode {
-            Mode::FullSpectrum => Ok((full_luminosity & 0xFFFF) as u16),
-            Mode::Infrared => Ok((full_luminosity >> 16) as u16),
-            Mode::Visible => {
-                let infrared_and_visible = full_luminosity & 0xFFFF;
-                let infrared = full_luminosity >> 16;
-                if infrared > infrared_and_visible {
-                    Err(Error::InfraredOverflow)
-                } else {
-                    Ok((infrared_and_visible - infrared) as u16)
+    ) -> Result<GainCalibration, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        let mut calibration = GainCalibration::NOMINAL;
+        let mut result = Ok(());
+        for &step in &Gain::ALL[1..] {
+            match self.measure_gain_step(delay, step) {
+                Ok(ratio) => calibration.set_step(step, ratio),
+                Err(e) => {
+                    result = Err(e);
+                    break;
                 }
             }
         }
-    }
 
-    pub fn calculate_lux<T: LuxConverter>(
-        &self,
-        ch_0: u16,
-        ch_1: u16,
-    ) -> Result<f32, Error<I2cError>> {
-        <T as LuxConverter>::calculate_lux(self.integration_time, self.gain, ch_0, ch_1)
-            .ok_or(Error::SignalOverflow)
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration()?;
+        result?;
+        Ok(calibration)
     }
 
-    pub fn calculate_nano_lux<T: LuxConverter>(
-        &self,
-        ch_0: u16,
-        ch_1: u16,
-    ) -> Result<i64, Error<I2cError>> {
-        <T as LuxConverter>::calculate_nano_lux(self.integration_time, self.gain, ch_0, ch_1)
-            .ok_or(Error::SignalOverflow)
+    /// Measure the ratio of `gain` to the next lower gain under steady light.
+    ///
+    /// Both gains are read several times with one-shot measurements at the
+    /// longest integration time where `gain` does not saturate. Returns `None`
+    /// if no integration time gives an unsaturated reading at `gain` together
+    /// with at least a hundred counts at the lower gain, or for [`Gain::Low`].
+    /// The cached configuration is restored afterwards.
+    pub fn calibrate_gain_step(
+        &mut self,
+        delay: &mut Delay,
+        gain: Gain,
+    ) -> Result<Option<f32>, Error<I2cError>> {
+        let (cached_gain, integration_time, enable) =
+            (self.gain, self.integration_time, self.enable);
+        let result = self.measure_gain_step(delay, gain);
+
+        self.gain = cached_gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration()?;
+        result
     }
-}
 
-#[cfg(feature = "async")]
-impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay>
-where
-    I2C: I2c<SevenBitAddress, Error = I2cError>,
-    Delay: DelayNs,
-{
-    pub async fn new(i2c: I2C) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
-        let mut driver = Tsl2591 {
-            i2c,
-            integration_time: IntegrationTime::_200MS,
-            gain: Gain::Low,
-            delay: PhantomData,
+    fn measure_gain_step(
+        &mut self,
+        delay: &mut Delay,
+        gain: Gain,
+    ) -> Result<Option<f32>, Error<I2cError>> {
+        let lower = match Gain::ALL.iter().position(|&step| step == gain) {
+            Some(i) if i > 0 => Gain::ALL[i - 1],
+            _ => return Ok(None),
         };
-        let id = driver.get_id().await?;
-        if id != chip::ID {
-            return Err(Error::IdMismatch(id));
+        let mut readings = [(Gain::Low, 0u16); 2 * GAIN_CALIBRATION_READINGS];
+        for &time in IntegrationTime::ALL.iter().rev() {
+            self.integration_time = time;
+            let mut saturated = false;
+            // The higher gain first, so a saturating time is skipped early
+            for (i, &step) in [gain, lower].iter().enumerate() {
+                self.gain = step;
+                for reading in readings[i * GAIN_CALIBRATION_READINGS..]
+                    .iter_mut()
+                    .take(GAIN_CALIBRATION_READINGS)
+                {
+                    let measurement = self.measure_once_inner(delay)?;
+                    saturated |= check_overflow(time, measurement.ch_0, 0);
+                    *reading = (step, measurement.ch_0);
+                }
+                if saturated {
+                    break;
+                }
+            }
+            if !saturated {
+                // Shorter times only give fewer counts at the lower gain
+                return Ok(GainCalibration::from_readings(time, &readings).step(gain));
+            }
         }
-        Ok(driver)
+        Ok(None)
     }
 
-    pub async fn new_define_integration(
-        i2c: I2C,
-        integration_time: IntegrationTime,
-        gain: Gain,
-    ) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
-        let mut driver = Tsl2591 {
-            i2c,
-            integration_time,
-            gain,
-            delay: PhantomData,
-        };
-        let id = driver.get_id().await?;
-        if id != chip::ID {
-            return Err(Error::IdMismatch(id));
+    /// Record the dark offset of both channels at every gain and integration
+    /// time.
+    ///
+    /// The sensor must be fully covered for the whole procedure, which takes
+    /// roughly `2 * 4 * 2.1` seconds. The cached configuration is restored
+    /// afterwards. Pass the result to
+    /// [`set_dark_offsets`](Self::set_dark_offsets) to use it.
+    pub fn calibrate_dark(&mut self, delay: &mut Delay) -> Result<DarkOffsets, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        let mut offsets = DarkOffsets::default();
+        let mut result = Ok(());
+        'settings: for &step_gain in &Gain::ALL {
+            for &step_time in &IntegrationTime::ALL {
+                self.gain = step_gain;
+                self.integration_time = step_time;
+                let (mut sum_0, mut sum_1) = (0u32, 0u32);
+                for _ in 0..DARK_CALIBRATION_READINGS {
+                    match self.measure_once_inner(delay) {
+                        Ok(measurement) => {
+                            sum_0 += measurement.ch_0 as u32;
+                            sum_1 += measurement.ch_1 as u32;
+                        }
+                        Err(e) => {
+                            result = Err(e);
+                            break 'settings;
+                        }
+                    }
+                }
+                offsets.set_offset(
+                    step_gain,
+                    step_time,
+                    (
+                        (sum_0 / DARK_CALIBRATION_READINGS) as u16,
+                        (sum_1 / DARK_CALIBRATION_READINGS) as u16,
+                    ),
+                );
+            }
         }
-        Ok(driver)
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration()?;
+        result?;
+        Ok(offsets)
     }
 
-    async fn get_id(&mut self) -> Result<u8, Error<I2cError>> {
-        let mut buffer = [0u8; 1];
-        self.i2c
-            .write_read(chip::I2C, &[chip::COMMAND_BIT | chip::ID_ADDR], &mut buffer)
-            .await?;
-        Ok(buffer[0])
+    /// Run a functional check of the sensor for production and field
+    /// diagnostics.
+    ///
+    /// Verifies the ID register against the part accepted at construction,
+    /// writes and reads back a pattern in the ALS threshold registers, triggers
+    /// the force-interrupt special function and checks AINT, waits for AVALID
+    /// within one fresh integration cycle and sanity-checks the resulting
+    /// channel data. The cached configuration is written back afterwards, also
+    /// when a transfer fails, and interrupts are cleared.
+    pub fn self_test(&mut self, delay: &mut Delay) -> Result<SelfTestReport, Error<I2cError>> {
+        let result = self.self_test_inner(delay);
+
+        self.restore_configuration()?;
+        self.write_special(chip::SF_CLEAR_ALL_INTERRUPTS)?;
+        result
     }
 
-    pub async fn set_gain(&mut self, gain: Option<Gain>) -> Result<(), Error<I2cError>> {
-        if let Some(gain) = gain {
-            self.i2c
-                .write(
-                    0x29,
-                    &[
-                        chip::COMMAND_BIT | chip::CONTROL,
-                        self.integration_time as u8 | gain as u8,
-                    ],
-                )
-                .await?;
-        } else {
-            self.i2c
-                .write(
-                    0x29,
-                    &[
-                        chip::COMMAND_BIT | chip::CONTROL,
-                        self.integration_time as u8 | self.gain as u8,
-                    ],
-                )
-                .await?;
+    fn self_test_inner(&mut self, delay: &mut Delay) -> Result<SelfTestReport, Error<I2cError>> {
+        let mut report = SelfTestReport::default();
+
+        report.id = self.get_id()?;
+        report.id_ok = report.id == self.device_info.id;
+
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, SELF_TEST_THRESHOLDS)?;
+        let readback = self.read_threshold_pair(chip::TSL2591_THRESHOLD_AILTL)?;
+        report.threshold_readback_ok = readback == SELF_TEST_THRESHOLDS;
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, self.thresholds)?;
+
+        // Disable the ALS first so AVALID from an earlier integration is
+        // cleared, then run it without interrupt enables so AINT only reflects
+        // the forced interrupt.
+        self.write_register(chip::ENABLE, Enable::default().0)?;
+        self.write_register(chip::CONTROL, self.control().0)?;
+        let mut enable = Enable::default();
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        self.write_register(chip::ENABLE, enable.0)?;
+        self.write_special(chip::SF_CLEAR_ALL_INTERRUPTS)?;
+        self.write_special(chip::SF_FORCE_INTERRUPT)?;
+        report.forced_interrupt_ok = self.get_status()?.AINT();
+        self.write_special(chip::SF_CLEAR_ALL_INTERRUPTS)?;
+
+        let deadline = self.integration_time.get_integration_time_millis() * 11 / 10;
+        let mut waited = 0;
+        while waited <= deadline {
+            if self.get_status()?.AVALID() {
+                report.valid_data_ok = true;
+                break;
+            }
+            delay.delay_ms(AVALID_POLL_MS);
+            waited += AVALID_POLL_MS;
         }
-        Ok(())
+
+        if report.valid_data_ok {
+            let (ch_0, ch_1) = self.read_channels()?;
+            report.channels = Some((ch_0, ch_1));
+            report.channel_data_ok =
+                !check_overflow(self.integration_time, ch_0, ch_1) && ch_1 <= ch_0;
+        }
+        Ok(report)
     }
 
-    pub async fn set_timing(
-        &mut self,
-        integration_time: Option<IntegrationTime>,
-    ) -> Result<(), Error<I2cError>> {
-        if let Some(integration_time) = integration_time {
-            self.i2c
-                .write(
-                    0x29,
-                    &[
-                        chip::COMMAND_BIT | chip::CONTROL,
-                        integration_time as u8 | self.gain as u8,
-                    ],
-                )
-                .await?;
+    /// Measure once at each of `gains` and merge the readings into a single
+    /// lux value with extended dynamic range.
+    ///
+    /// Saturated readings are discarded; the rest are averaged weighted by
+    /// their CH0 counts, which under shot noise is the squared signal-to-noise
+    /// ratio. The cached gain and enable state are restored afterwards. Returns
+    /// [`Error::SignalOverflow`] if no reading could be converted.
+    pub fn hdr_lux(&mut self, delay: &mut Delay, gains: &[Gain]) -> Result<f32, Error<I2cError>> {
+        let (gain, enable) = (self.gain, self.enable);
+        let (mut weighted_sum, mut total_weight) = (0.0f32, 0.0f32);
+        let mut result = Ok(());
+        for &step in gains {
+            self.gain = step;
+            let measurement = match self.measure_once_inner(delay) {
+                Ok(measurement) => measurement,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            if check_overflow(
+                measurement.integration_time,
+                measurement.ch_0,
+                measurement.ch_1,
+            ) {
+                continue;
+            }
+            if let Ok(lux) = self.lux(measurement.ch_0, measurement.ch_1) {
+                let weight = measurement.ch_0.max(1) as f32;
+                weighted_sum += lux * weight;
+                total_weight += weight;
+            }
+        }
+
+        self.gain = gain;
+        self.enable = enable;
+        self.restore_configuration()?;
+        result?;
+        if total_weight > 0.0 {
+            Ok(weighted_sum / total_weight)
         } else {
-            self.i2c
-                .write(
-                    0x29,
-                    &[
-                        chip::COMMAND_BIT | chip::CONTROL,
-                        self.integration_time as u8 | self.gain as u8,
-                    ],
-                )
-                .await?;
+            Err(Error::SignalOverflow)
         }
-        Ok(())
     }
 
-    pub async fn disable(&mut self) -> Result<(), Error<I2cError>> {
-        self.i2c
-            .write(
-                0x29,
-                &[chip::COMMAND_BIT | chip::ENABLE, chip::ENABLE_POWEROFF],
-            )
-            .await?;
-        Ok(())
+    /// One-shot lux measurement that degrades gracefully on saturation.
+    ///
+    /// If the reading at the cached settings overflows, the sensor is measured
+    /// again at [`Gain::Low`] and [`IntegrationTime::_100MS`]. If that
+    /// saturates as well, the lux at the largest CH0 count that does not
+    /// overflow is returned as [`LuxEstimate::AtLeast`]; a saturated CH1 is
+    /// taken as at most 0.6 times CH0, as under incandescent light. Returns
+    /// [`Error::SignalOverflow`] if that gives no positive bound with the
+    /// selected algorithm. The cached configuration is restored afterwards.
+    pub fn measure_lux(&mut self, delay: &mut Delay) -> Result<LuxEstimate, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        let result = self.measure_lux_inner(delay);
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration()?;
+        result
     }
 
+    fn measure_lux_inner(&mut self, delay: &mut Delay) -> Result<LuxEstimate, Error<I2cError>> {
+        let measurement = self.measure_once_inner(delay)?;
+        if !check_overflow(self.integration_time, measurement.ch_0, measurement.ch_1) {
+            return self
+                .lux(measurement.ch_0, measurement.ch_1)
+                .map(LuxEstimate::Exact);
+        }
+
+        self.gain = Gain::Low;
+        self.integration_time = IntegrationTime::_100MS;
+        let measurement = self.measure_once_inner(delay)?;
+        self.saturation_estimate(measurement)
+    }
+
+    fn saturation_estimate(
+        &self,
+        measurement: Measurement,
+    ) -> Result<LuxEstimate, Error<I2cError>> {
+        if !check_overflow(self.integration_time, measurement.ch_0, measurement.ch_1) {
+            return self
+                .lux(measurement.ch_0, measurement.ch_1)
+                .map(LuxEstimate::Exact);
+        }
+        let (ch_0, ch_1) =
+            saturation_bound_counts(self.integration_time, measurement.ch_0, measurement.ch_1);
+        match self.lux(ch_0, ch_1)? {
+            lux if lux > 0.0 => Ok(LuxEstimate::AtLeast(lux)),
+            _ => Err(Error::SignalOverflow),
+        }
+    }
+
+    /// Measure sky brightness at [`Gain::Max`] and [`IntegrationTime::_600MS`].
+    ///
+    /// One-shot integrations are accumulated until `config.target_counts`
+    /// visible counts are reached or `config.max_integrations` have run. The
+    /// dark offset for these settings is subtracted from the sums and the gain
+    /// calibration applied. Returns [`Error::SignalOverflow`] if the sky is too
+    /// bright for these settings. The cached configuration is restored
+    /// afterwards.
+    pub fn measure_sky_quality(
+        &mut self,
+        delay: &mut Delay,
+        config: &SqmConfig,
+    ) -> Result<SkyQuality, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        self.gain = Gain::Max;
+        self.integration_time = IntegrationTime::_600MS;
+        let (dark_0, dark_1) = self.dark_offsets.offset(self.gain, self.integration_time);
+        let (mut sum_0, mut sum_1, mut integrations) = (0u32, 0u32, 0u16);
+        let mut result = Ok(());
+        while integrations < config.max_integrations.max(1) {
+            match self.measure_once_inner(delay) {
+                Ok(measurement) => {
+                    if check_overflow(self.integration_time, measurement.ch_0, measurement.ch_1) {
+                        result = Err(Error::SignalOverflow);
+                        break;
+                    }
+                    sum_0 += measurement.ch_0 as u32;
+                    sum_1 += measurement.ch_1 as u32;
+                    integrations += 1;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            let ch_0 = sum_0.saturating_sub(dark_0 as u32 * integrations as u32);
+            let ch_1 = sum_1.saturating_sub(dark_1 as u32 * integrations as u32);
+            if ch_0.saturating_sub(ch_1) >= config.target_counts {
+                break;
+            }
+        }
+        let ch_0_counts = sum_0.saturating_sub(dark_0 as u32 * integrations as u32);
+        let ch_1_counts = sum_1.saturating_sub(dark_1 as u32 * integrations as u32);
+        let visible = ch_0_counts.saturating_sub(ch_1_counts) as f32
+            * self.gain_calibration.correction(self.gain);
+        let magnitude = config.magnitude(self.integration_time, visible, integrations);
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration()?;
+        result?;
+        Ok(SkyQuality {
+            magnitude,
+            ch_0_counts,
+            ch_1_counts,
+            integrations,
+        })
+    }
+
+    /// Calculate lux with the algorithm selected by
+    /// [`set_lux_algorithm`](Self::set_lux_algorithm).
+    pub fn lux(&self, ch_0: u16, ch_1: u16) -> Result<f32, Error<I2cError>> {
+        self.lux_with(&self.lux_algorithm, ch_0, ch_1)
+    }
+
+    pub fn nano_lux(&self, ch_0: u16, ch_1: u16) -> Result<i64, Error<I2cError>> {
+        self.nano_lux_with(&self.lux_algorithm, ch_0, ch_1)
+    }
+
+    /// Calculate lux with any converter, e.g. one wrapped in
+    /// [`TemperatureCompensated`](crate::TemperatureCompensated). Dark offsets
+    /// and the gain calibration are applied as in [`lux`](Self::lux), so don't
+    /// also wrap the converter in [`DarkCompensated`](crate::DarkCompensated)
+    /// while [`set_dark_offsets`](Self::set_dark_offsets) is in use.
+    pub fn lux_with<C: DynLuxConverter + ?Sized>(
+        &self,
+        converter: &C,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<f32, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        converter
+            .lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    pub fn nano_lux_with<C: DynLuxConverter + ?Sized>(
+        &self,
+        converter: &C,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<i64, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        converter
+            .nano_lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_nano_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    /// Lux from the selected algorithm with an uncertainty estimate and
+    /// quality flags, after dark and gain calibration.
+    pub fn lux_quality(
+        &self,
+        model: &QualityModel,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<LuxQuality, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        model
+            .assess(
+                &self.lux_algorithm,
+                self.integration_time,
+                self.gain,
+                ch_0,
+                ch_1,
+            )
+            .map(|quality| quality.scaled(self.gain_calibration.correction(self.gain)))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    /// Irradiance estimated with `model`, after dark and gain calibration.
+    pub fn irradiance(
+        &self,
+        model: &SpectralModel,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<Irradiance, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        let correction = self.gain_calibration.correction(self.gain);
+        model
+            .irradiance(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|irradiance| Irradiance {
+                visible: irradiance.visible * correction,
+                infrared: irradiance.infrared * correction,
+                total: irradiance.total * correction,
+            })
+            .ok_or(Error::SignalOverflow)
+    }
+
+    pub fn calculate_lux<T: LuxConverter>(
+        &self,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<f32, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        <T as LuxConverter>::calculate_lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    pub fn calculate_nano_lux<T: LuxConverter>(
+        &self,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<i64, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        <T as LuxConverter>::calculate_nano_lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_nano_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<I2C, I2cError, Delay, S> Tsl2591<I2C, Delay, S>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+    S: Measure,
+{
+    pub fn get_channel_data(&mut self, delay: &mut Delay) -> Result<(u16, u16), Error<I2cError>> {
+        delay.delay_ms(120);
+        self.read_channels()
+    }
+
+    pub fn get_luminosity(
+        &mut self,
+        mode: Mode,
+        delay: &mut Delay,
+    ) -> Result<u16, Error<I2cError>> {
+        let (channel_0, channel_1) = self.get_channel_data(delay)?;
+        let full_luminosity: u32 = ((channel_1 as u32) << 16) | channel_0 as u32;
+
+        match mode {
+            Mode::FullSpectrum => Ok((full_luminosity & 0xFFFF) as u16),
+            Mode::Infrared => Ok((full_luminosity >> 16) as u16),
+            Mode::Visible => {
+                let infrared_and_visible = full_luminosity & 0xFFFF;
+                let infrared = full_luminosity >> 16;
+                if infrared > infrared_and_visible {
+                    Err(Error::InfraredOverflow)
+                } else {
+                    Ok((infrared_and_visible - infrared) as u16)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay, Off>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+{
+    /// Power the sensor on and enable the ALS.
+    pub fn enable(mut self) -> Result<Tsl2591<I2C, Delay, On>, Error<I2cError>> {
+        self.power_on()?;
+        Ok(self.into_state())
+    }
+
+    /// Take a single measurement with minimal on-time.
+    ///
+    /// Powers the sensor on with the cached gain and integration time, waits
+    /// for exactly one valid integration cycle, reads both channels and powers
+    /// the sensor off again.
+    pub fn measure_once(&mut self, delay: &mut Delay) -> Result<Measurement, Error<I2cError>> {
+        self.measure_once_inner(delay)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay, On>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+{
+    /// Power the sensor off.
+    pub fn disable(mut self) -> Result<Tsl2591<I2C, Delay, Off>, Error<I2cError>> {
+        self.power_off()?;
+        Ok(self.into_state())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+{
+    pub async fn new(i2c: I2C) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
+        Self::new_with_allowed_ids(i2c, IntegrationTime::_200MS, Gain::Low, &[chip::ID]).await
+    }
+
+    pub async fn new_define_integration(
+        i2c: I2C,
+        integration_time: IntegrationTime,
+        gain: Gain,
+    ) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
+        Self::new_with_allowed_ids(i2c, integration_time, gain, &[chip::ID]).await
+    }
+
+    /// Like [`new_define_integration`](Self::new_define_integration), but
+    /// accepts any part whose ID register is in `allowed_ids` instead of only
+    /// the TSL2591 ID. Useful for pin-compatible variants.
+    pub async fn new_with_allowed_ids(
+        i2c: I2C,
+        integration_time: IntegrationTime,
+        gain: Gain,
+        allowed_ids: &[u8],
+    ) -> Result<Tsl2591<I2C, Delay>, Error<I2cError>> {
+        // Powering on enables both interrupts until a Config says otherwise.
+        let mut enable_flags = Enable::default();
+        enable_flags.set_AIEN(true);
+        enable_flags.set_NPIEN(true);
+        let mut driver = Tsl2591 {
+            i2c,
+            integration_time,
+            gain,
+            enable: Enable::default(),
+            enable_flags,
+            thresholds: (0, 0),
+            no_persist_thresholds: (0, 0),
+            persistence: Persistence::Every,
+            device_info: DeviceInfo::default(),
+            lux_algorithm: LuxAlgorithm::default(),
+            gain_calibration: GainCalibration::default(),
+            dark_offsets: DarkOffsets::default(),
+            delay: PhantomData,
+            state: PhantomData,
+        };
+        let device_info = driver.get_device_info().await?;
+        if !allowed_ids.contains(&device_info.id) {
+            return Err(Error::IdMismatch(device_info));
+        }
+        driver.device_info = device_info;
+        // The part powers up with its own CONTROL defaults; write the cached
+        // gain and integration time so the two agree from the start.
+        driver
+            .write_register(chip::CONTROL, driver.control().0)
+            .await?;
+        Ok(driver)
+    }
+
+    pub async fn disable(&mut self) -> Result<(), Error<I2cError>> {
+        self.power_off().await
+    }
+
+    /// Power the sensor on and enable the ALS, with the interrupt and
+    /// sleep-after-interrupt flags of the last applied [`Config`] (AIEN and
+    /// NPIEN until one is applied).
     pub async fn enable(&mut self) -> Result<(), Error<I2cError>> {
+        self.power_on().await
+    }
+
+    /// Write a complete configuration to the sensor.
+    ///
+    /// The ALS is held disabled while CONTROL, the thresholds and the
+    /// persistence filter are written, and ENABLE is written last, so the
+    /// sensor never integrates with a partial configuration. If a write fails,
+    /// the configuration is still cached and can be retried with
+    /// [`restore_configuration`](Self::restore_configuration).
+    pub async fn apply_config(&mut self, config: &Config) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::ENABLE, Enable::default().0)
+            .await?;
+        self.integration_time = config.integration_time;
+        self.gain = config.gain;
+        self.enable = config.enable;
+        self.enable_flags = config.enable;
+        self.enable_flags.set_PON(false);
+        self.enable_flags.set_AEN(false);
+        self.thresholds = config.thresholds;
+        self.no_persist_thresholds = config.no_persist_thresholds;
+        self.persistence = config.persistence;
+        self.restore_configuration().await
+    }
+
+    /// Power the sensor off and track its power state in the type system from
+    /// here on.
+    pub async fn into_off(mut self) -> Result<Tsl2591<I2C, Delay, Off>, Error<I2cError>> {
+        self.power_off().await?;
+        Ok(self.into_state())
+    }
+
+    /// Take a single measurement with minimal on-time.
+    ///
+    /// Powers the sensor on with the cached gain and integration time, waits
+    /// for exactly one valid integration cycle, reads both channels and powers
+    /// the sensor off again.
+    pub async fn measure_once(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<Measurement, Error<I2cError>> {
+        self.measure_once_inner(delay).await
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, I2cError, Delay, S> Tsl2591<I2C, Delay, S>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+    S: PowerState,
+{
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    pub fn integration_time(&self) -> IntegrationTime {
+        self.integration_time
+    }
+
+    /// Select the algorithm used by [`lux`](Self::lux) and
+    /// [`nano_lux`](Self::nano_lux).
+    pub fn set_lux_algorithm(&mut self, lux_algorithm: LuxAlgorithm) {
+        self.lux_algorithm = lux_algorithm;
+    }
+
+    pub fn lux_algorithm(&self) -> LuxAlgorithm {
+        self.lux_algorithm
+    }
+
+    /// Use measured gain multipliers for every lux calculation.
+    pub fn set_gain_calibration(&mut self, gain_calibration: GainCalibration) {
+        self.gain_calibration = gain_calibration;
+    }
+
+    pub fn gain_calibration(&self) -> GainCalibration {
+        self.gain_calibration
+    }
+
+    /// Subtract dark offsets from the channel data before every lux
+    /// calculation.
+    pub fn set_dark_offsets(&mut self, dark_offsets: DarkOffsets) {
+        self.dark_offsets = dark_offsets;
+    }
+
+    pub fn dark_offsets(&self) -> DarkOffsets {
+        self.dark_offsets
+    }
+
+    fn dark_compensate(&self, ch_0: u16, ch_1: u16) -> Result<(u16, u16), Error<I2cError>> {
+        if check_overflow(self.integration_time, ch_0, ch_1) {
+            return Err(Error::SignalOverflow);
+        }
+        Ok(self
+            .dark_offsets
+            .compensate(self.gain, self.integration_time, ch_0, ch_1))
+    }
+
+    /// The configuration the driver last wrote to the sensor. While the sensor
+    /// is powered off, the enable flags it will be powered on with are
+    /// reported without PON and AEN.
+    pub fn config(&self) -> Config {
+        Config {
+            gain: self.gain,
+            integration_time: self.integration_time,
+            enable: Enable(self.enable.0 | self.enable_flags.0),
+            thresholds: self.thresholds,
+            no_persist_thresholds: self.no_persist_thresholds,
+            persistence: self.persistence,
+        }
+    }
+
+    async fn get_id(&mut self) -> Result<u8, Error<I2cError>> {
+        let mut buffer = [0u8; 1];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | chip::ID_ADDR], &mut buffer)
+            .await?;
+        Ok(buffer[0])
+    }
+
+    async fn get_package_id(&mut self) -> Result<u8, Error<I2cError>> {
+        let mut buffer = [0u8; 1];
+        self.i2c
+            .write_read(
+                chip::I2C,
+                &[chip::COMMAND_BIT | chip::PID_ADDR],
+                &mut buffer,
+            )
+            .await?;
+        Ok(buffer[0])
+    }
+
+    /// Read the ID and package ID registers from the sensor.
+    pub async fn get_device_info(&mut self) -> Result<DeviceInfo, Error<I2cError>> {
+        Ok(DeviceInfo {
+            id: self.get_id().await?,
+            package_id: self.get_package_id().await?,
+        })
+    }
+
+    /// The device info read when the driver was constructed.
+    pub fn device_info(&self) -> DeviceInfo {
+        self.device_info
+    }
+
+    fn control(&self) -> Control {
+        Control::new(self.integration_time, self.gain)
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, Error<I2cError>> {
+        let mut buffer = [0u8; 1];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | register], &mut buffer)
+            .await?;
+        Ok(buffer[0])
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error<I2cError>> {
+        self.i2c
+            .write(chip::I2C, &[chip::COMMAND_BIT | register, value])
+            .await?;
+        Ok(())
+    }
+
+    async fn write_threshold_pair(
+        &mut self,
+        register: u8,
+        (low, high): (u16, u16),
+    ) -> Result<(), Error<I2cError>> {
+        let [low_l, low_h] = low.to_le_bytes();
+        let [high_l, high_h] = high.to_le_bytes();
         self.i2c
             .write(
-                0x29,
-                &[
-                    chip::COMMAND_BIT | chip::ENABLE,
-                    chip::ENABLE_POWERON
-                        | chip::ENABLE_AEN
-                        | chip::ENABLE_AIEN
-                        | chip::ENABLE_NPIEN,
-                ],
+                chip::I2C,
+                &[chip::COMMAND_BIT | register, low_l, low_h, high_l, high_h],
             )
             .await?;
         Ok(())
     }
 
+    async fn read_threshold_pair(&mut self, register: u8) -> Result<(u16, u16), Error<I2cError>> {
+        let mut buffer = [0u8; 4];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | register], &mut buffer)
+            .await?;
+        Ok((
+            u16::from_le_bytes([buffer[0], buffer[1]]),
+            u16::from_le_bytes([buffer[2], buffer[3]]),
+        ))
+    }
+
+    async fn write_special(&mut self, function: u8) -> Result<(), Error<I2cError>> {
+        self.i2c
+            .write(chip::I2C, &[chip::SPECIAL_FUNCTION | function])
+            .await?;
+        Ok(())
+    }
+
+    async fn read_channels(&mut self) -> Result<(u16, u16), Error<I2cError>> {
+        let mut buffer = [0u8; 4];
+        self.i2c
+            .write_read(
+                chip::I2C,
+                &[chip::COMMAND_BIT | chip::CHAN0_LOW],
+                &mut buffer,
+            )
+            .await?;
+        let channel_0 = u16::from_le_bytes([buffer[0], buffer[1]]);
+        let channel_1 = u16::from_le_bytes([buffer[2], buffer[3]]);
+        Ok((channel_0, channel_1))
+    }
+
+    pub async fn set_gain(&mut self, gain: Option<Gain>) -> Result<(), Error<I2cError>> {
+        if let Some(gain) = gain {
+            self.gain = gain;
+        }
+        self.write_register(chip::CONTROL, self.control().0).await
+    }
+
+    pub async fn set_timing(
+        &mut self,
+        integration_time: Option<IntegrationTime>,
+    ) -> Result<(), Error<I2cError>> {
+        if let Some(integration_time) = integration_time {
+            self.integration_time = integration_time;
+        }
+        self.write_register(chip::CONTROL, self.control().0).await
+    }
+
+    /// Set the low and high ALS thresholds used by the persistent interrupt.
+    pub async fn set_thresholds(&mut self, low: u16, high: u16) -> Result<(), Error<I2cError>> {
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, (low, high))
+            .await?;
+        self.thresholds = (low, high);
+        Ok(())
+    }
+
+    /// Set the low and high ALS thresholds used by the no-persist interrupt.
+    pub async fn set_no_persist_thresholds(
+        &mut self,
+        low: u16,
+        high: u16,
+    ) -> Result<(), Error<I2cError>> {
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_NPAILTL, (low, high))
+            .await?;
+        self.no_persist_thresholds = (low, high);
+        Ok(())
+    }
+
+    pub async fn set_persistence(
+        &mut self,
+        persistence: Persistence,
+    ) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::TSL2591_PERSIST_FILTER, persistence as u8)
+            .await?;
+        self.persistence = persistence;
+        Ok(())
+    }
+
+    async fn power_off(&mut self) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::ENABLE, Enable::default().0)
+            .await?;
+        self.enable = Enable::default();
+        Ok(())
+    }
+
+    async fn power_on(&mut self) -> Result<(), Error<I2cError>> {
+        let mut enable = self.enable_flags;
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        self.write_register(chip::ENABLE, enable.0).await?;
+        self.enable = enable;
+        Ok(())
+    }
+
+    /// Write the driver's cached configuration (gain, integration time,
+    /// thresholds, persistence and enable flags) back to the sensor.
+    pub async fn restore_configuration(&mut self) -> Result<(), Error<I2cError>> {
+        self.write_register(chip::CONTROL, self.control().0).await?;
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, self.thresholds)
+            .await?;
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_NPAILTL, self.no_persist_thresholds)
+            .await?;
+        self.write_register(chip::TSL2591_PERSIST_FILTER, self.persistence as u8)
+            .await?;
+        self.write_register(chip::ENABLE, self.enable.0).await
+    }
+
+    /// Detect whether the sensor lost power since it was configured and, if
+    /// so, restore the configuration.
+    ///
+    /// A brown-out is assumed when ENABLE reads as powered off although the
+    /// driver powered the sensor on, or when CONTROL no longer matches the
+    /// cached gain and integration time. Returns `true` if a recovery happened.
+    pub async fn check_brown_out(&mut self) -> Result<bool, Error<I2cError>> {
+        let enable = Enable(self.read_register(chip::ENABLE).await?);
+        let control = Control(self.read_register(chip::CONTROL).await?);
+
+        let lost_power = self.enable.PON() && !enable.PON();
+        let control_reset = control.0 & CONTROL_READBACK_MASK != self.control().0;
+        if lost_power || control_reset {
+            self.restore_configuration().await?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     pub async fn get_enable(&mut self) -> Result<Enable, Error<I2cError>> {
         let mut status = [0u8; 1];
         self.i2c
@@ -340,6 +1423,10 @@ where
         Ok(Enable(status[0]))
     }
 
+    pub async fn get_control(&mut self) -> Result<Control, Error<I2cError>> {
+        Ok(Control(self.read_register(chip::CONTROL).await?))
+    }
+
     pub async fn get_status(&mut self) -> Result<Status, Error<I2cError>> {
         let mut status = [0u8; 1];
         self.i2c
@@ -348,30 +1435,559 @@ where
         Ok(Status(status[0]))
     }
 
+    /// Burst-read every register from ENABLE (0x00) to C1DATAH (0x17).
+    pub async fn get_register_snapshot(&mut self) -> Result<RegisterSnapshot, Error<I2cError>> {
+        let mut raw = [0u8; chip::REGISTER_COUNT];
+        self.i2c
+            .write_read(chip::I2C, &[chip::COMMAND_BIT | chip::ENABLE], &mut raw)
+            .await?;
+        Ok(RegisterSnapshot::from_raw(raw))
+    }
+
+    async fn measure_once_inner(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<Measurement, Error<I2cError>> {
+        // Always try to power off, even after a failed transfer, so a bus
+        // glitch doesn't leave the ADC running; the first error wins.
+        let channels = self.integrate_once(delay).await;
+        let powered_off = self.power_off().await;
+        let (ch_0, ch_1) = channels?;
+        powered_off?;
+        Ok(Measurement {
+            ch_0,
+            ch_1,
+            integration_time: self.integration_time,
+            gain: self.gain,
+        })
+    }
+
+    async fn integrate_once(&mut self, delay: &mut Delay) -> Result<(u16, u16), Error<I2cError>> {
+        // Stop a running ALS first, so AVALID and the channel data can't come
+        // from a cycle that started under the previous gain or time.
+        self.write_register(chip::ENABLE, Enable::default().0)
+            .await?;
+        self.write_register(chip::CONTROL, self.control().0).await?;
+        let mut enable = Enable::default();
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        self.write_register(chip::ENABLE, enable.0).await?;
+
+        // The first cycle completes one integration time after AEN is set;
+        // poll for the remainder to absorb oscillator tolerance.
+        let integration_ms = self.integration_time.get_integration_time_millis();
+        delay.delay_ms(integration_ms).await;
+        let mut waited = 0;
+        loop {
+            if self.get_status().await?.AVALID() {
+                return self.read_channels().await;
+            }
+            if waited >= integration_ms {
+                return Err(Error::Timeout);
+            }
+            delay.delay_ms(AVALID_POLL_MS).await;
+            waited += AVALID_POLL_MS;
+        }
+    }
+
+    /// Measure the gain steps the current light allows.
+    ///
+    /// Each step compares a gain with the next lower one, see
+    /// [`calibrate_gain_step`](Self::calibrate_gain_step). One light level
+    /// can't serve every step: Low to Med needs bright light, High to Max light
+    /// several hundred times dimmer. Steps the light doesn't allow are left
+    /// unmeasured, as reported by [`GainCalibration::steps`]; repeat under
+    /// other light and combine the results with [`GainCalibration::merge`]
+    /// before passing them to
+    /// [`set_gain_calibration`](Self::set_gain_calibration). The cached
+    /// configuration is restored afterwards.
+    pub async fn calibrate_gain(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<GainCalibration, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        let mut calibration = GainCalibration::NOMINAL;
+        let mut result = Ok(());
+        for &step in &Gain::ALL[1..] {
+            match self.measure_gain_step(delay, step).await {
+                Ok(ratio) => calibration.set_step(step, ratio),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration().await?;
+        result?;
+        Ok(calibration)
+    }
+
+    /// Measure the ratio of `gain` to the next lower gain under steady light.
+    ///
+    /// Both gains are read several times with one-shot measurements at the
+    /// longest integration time where `gain` does not saturate. Returns `None`
+    /// if no integration time gives an unsaturated reading at `gain` together
+    /// with at least a hundred counts at the lower gain, or for [`Gain::Low`].
+    /// The cached configuration is restored afterwards.
+    pub async fn calibrate_gain_step(
+        &mut self,
+        delay: &mut Delay,
+        gain: Gain,
+    ) -> Result<Option<f32>, Error<I2cError>> {
+        let (cached_gain, integration_time, enable) =
+            (self.gain, self.integration_time, self.enable);
+        let result = self.measure_gain_step(delay, gain).await;
+
+        self.gain = cached_gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration().await?;
+        result
+    }
+
+    async fn measure_gain_step(
+        &mut self,
+        delay: &mut Delay,
+        gain: Gain,
+    ) -> Result<Option<f32>, Error<I2cError>> {
+        let lower = match Gain::ALL.iter().position(|&step| step == gain) {
+            Some(i) if i > 0 => Gain::ALL[i - 1],
+            _ => return Ok(None),
+        };
+        let mut readings = [(Gain::Low, 0u16); 2 * GAIN_CALIBRATION_READINGS];
+        for &time in IntegrationTime::ALL.iter().rev() {
+            self.integration_time = time;
+            let mut saturated = false;
+            // The higher gain first, so a saturating time is skipped early
+            for (i, &step) in [gain, lower].iter().enumerate() {
+                self.gain = step;
+                for reading in readings[i * GAIN_CALIBRATION_READINGS..]
+                    .iter_mut()
+                    .take(GAIN_CALIBRATION_READINGS)
+                {
+                    let measurement = self.measure_once_inner(delay).await?;
+                    saturated |= check_overflow(time, measurement.ch_0, 0);
+                    *reading = (step, measurement.ch_0);
+                }
+                if saturated {
+                    break;
+                }
+            }
+            if !saturated {
+                // Shorter times only give fewer counts at the lower gain
+                return Ok(GainCalibration::from_readings(time, &readings).step(gain));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Record the dark offset of both channels at every gain and integration
+    /// time.
+    ///
+    /// The sensor must be fully covered for the whole procedure, which takes
+    /// roughly `2 * 4 * 2.1` seconds. The cached configuration is restored
+    /// afterwards. Pass the result to
+    /// [`set_dark_offsets`](Self::set_dark_offsets) to use it.
+    pub async fn calibrate_dark(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<DarkOffsets, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        let mut offsets = DarkOffsets::default();
+        let mut result = Ok(());
+        'settings: for &step_gain in &Gain::ALL {
+            for &step_time in &IntegrationTime::ALL {
+                self.gain = step_gain;
+                self.integration_time = step_time;
+                let (mut sum_0, mut sum_1) = (0u32, 0u32);
+                for _ in 0..DARK_CALIBRATION_READINGS {
+                    match self.measure_once_inner(delay).await {
+                        Ok(measurement) => {
+                            sum_0 += measurement.ch_0 as u32;
+                            sum_1 += measurement.ch_1 as u32;
+                        }
+                        Err(e) => {
+                            result = Err(e);
+                            break 'settings;
+                        }
+                    }
+                }
+                offsets.set_offset(
+                    step_gain,
+                    step_time,
+                    (
+                        (sum_0 / DARK_CALIBRATION_READINGS) as u16,
+                        (sum_1 / DARK_CALIBRATION_READINGS) as u16,
+                    ),
+                );
+            }
+        }
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration().await?;
+        result?;
+        Ok(offsets)
+    }
+
+    /// Run a functional check of the sensor for production and field
+    /// diagnostics.
+    ///
+    /// Verifies the ID register against the part accepted at construction,
+    /// writes and reads back a pattern in the ALS threshold registers, triggers
+    /// the force-interrupt special function and checks AINT, waits for AVALID
+    /// within one fresh integration cycle and sanity-checks the resulting
+    /// channel data. The cached configuration is written back afterwards, also
+    /// when a transfer fails, and interrupts are cleared.
+    pub async fn self_test(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<SelfTestReport, Error<I2cError>> {
+        let result = self.self_test_inner(delay).await;
+
+        self.restore_configuration().await?;
+        self.write_special(chip::SF_CLEAR_ALL_INTERRUPTS).await?;
+        result
+    }
+
+    async fn self_test_inner(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<SelfTestReport, Error<I2cError>> {
+        let mut report = SelfTestReport::default();
+
+        report.id = self.get_id().await?;
+        report.id_ok = report.id == self.device_info.id;
+
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, SELF_TEST_THRESHOLDS)
+            .await?;
+        let readback = self
+            .read_threshold_pair(chip::TSL2591_THRESHOLD_AILTL)
+            .await?;
+        report.threshold_readback_ok = readback == SELF_TEST_THRESHOLDS;
+        self.write_threshold_pair(chip::TSL2591_THRESHOLD_AILTL, self.thresholds)
+            .await?;
+
+        // Disable the ALS first so AVALID from an earlier integration is
+        // cleared, then run it without interrupt enables so AINT only reflects
+        // the forced interrupt.
+        self.write_register(chip::ENABLE, Enable::default().0)
+            .await?;
+        self.write_register(chip::CONTROL, self.control().0).await?;
+        let mut enable = Enable::default();
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        self.write_register(chip::ENABLE, enable.0).await?;
+        self.write_special(chip::SF_CLEAR_ALL_INTERRUPTS).await?;
+        self.write_special(chip::SF_FORCE_INTERRUPT).await?;
+        report.forced_interrupt_ok = self.get_status().await?.AINT();
+        self.write_special(chip::SF_CLEAR_ALL_INTERRUPTS).await?;
+
+        let deadline = self.integration_time.get_integration_time_millis() * 11 / 10;
+        let mut waited = 0;
+        while waited <= deadline {
+            if self.get_status().await?.AVALID() {
+                report.valid_data_ok = true;
+                break;
+            }
+            delay.delay_ms(AVALID_POLL_MS).await;
+            waited += AVALID_POLL_MS;
+        }
+
+        if report.valid_data_ok {
+            let (ch_0, ch_1) = self.read_channels().await?;
+            report.channels = Some((ch_0, ch_1));
+            report.channel_data_ok =
+                !check_overflow(self.integration_time, ch_0, ch_1) && ch_1 <= ch_0;
+        }
+        Ok(report)
+    }
+
+    /// Measure once at each of `gains` and merge the readings into a single
+    /// lux value with extended dynamic range.
+    ///
+    /// Saturated readings are discarded; the rest are averaged weighted by
+    /// their CH0 counts, which under shot noise is the squared signal-to-noise
+    /// ratio. The cached gain and enable state are restored afterwards. Returns
+    /// [`Error::SignalOverflow`] if no reading could be converted.
+    pub async fn hdr_lux(
+        &mut self,
+        delay: &mut Delay,
+        gains: &[Gain],
+    ) -> Result<f32, Error<I2cError>> {
+        let (gain, enable) = (self.gain, self.enable);
+        let (mut weighted_sum, mut total_weight) = (0.0f32, 0.0f32);
+        let mut result = Ok(());
+        for &step in gains {
+            self.gain = step;
+            let measurement = match self.measure_once_inner(delay).await {
+                Ok(measurement) => measurement,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            if check_overflow(
+                measurement.integration_time,
+                measurement.ch_0,
+                measurement.ch_1,
+            ) {
+                continue;
+            }
+            if let Ok(lux) = self.lux(measurement.ch_0, measurement.ch_1) {
+                let weight = measurement.ch_0.max(1) as f32;
+                weighted_sum += lux * weight;
+                total_weight += weight;
+            }
+        }
+
+        self.gain = gain;
+        self.enable = enable;
+        self.restore_configuration().await?;
+        result?;
+        if total_weight > 0.0 {
+            Ok(weighted_sum / total_weight)
+        } else {
+            Err(Error::SignalOverflow)
+        }
+    }
+
+    /// One-shot lux measurement that degrades gracefully on saturation.
+    ///
+    /// If the reading at the cached settings overflows, the sensor is measured
+    /// again at [`Gain::Low`] and [`IntegrationTime::_100MS`]. If that
+    /// saturates as well, the lux at the largest CH0 count that does not
+    /// overflow is returned as [`LuxEstimate::AtLeast`]; a saturated CH1 is
+    /// taken as at most 0.6 times CH0, as under incandescent light. Returns
+    /// [`Error::SignalOverflow`] if that gives no positive bound with the
+    /// selected algorithm. The cached configuration is restored afterwards.
+    pub async fn measure_lux(&mut self, delay: &mut Delay) -> Result<LuxEstimate, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        let result = self.measure_lux_inner(delay).await;
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration().await?;
+        result
+    }
+
+    async fn measure_lux_inner(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<LuxEstimate, Error<I2cError>> {
+        let measurement = self.measure_once_inner(delay).await?;
+        if !check_overflow(self.integration_time, measurement.ch_0, measurement.ch_1) {
+            return self
+                .lux(measurement.ch_0, measurement.ch_1)
+                .map(LuxEstimate::Exact);
+        }
+
+        self.gain = Gain::Low;
+        self.integration_time = IntegrationTime::_100MS;
+        let measurement = self.measure_once_inner(delay).await?;
+        self.saturation_estimate(measurement)
+    }
+
+    fn saturation_estimate(
+        &self,
+        measurement: Measurement,
+    ) -> Result<LuxEstimate, Error<I2cError>> {
+        if !check_overflow(self.integration_time, measurement.ch_0, measurement.ch_1) {
+            return self
+                .lux(measurement.ch_0, measurement.ch_1)
+                .map(LuxEstimate::Exact);
+        }
+        let (ch_0, ch_1) =
+            saturation_bound_counts(self.integration_time, measurement.ch_0, measurement.ch_1);
+        match self.lux(ch_0, ch_1)? {
+            lux if lux > 0.0 => Ok(LuxEstimate::AtLeast(lux)),
+            _ => Err(Error::SignalOverflow),
+        }
+    }
+
+    /// Measure sky brightness at [`Gain::Max`] and [`IntegrationTime::_600MS`].
+    ///
+    /// One-shot integrations are accumulated until `config.target_counts`
+    /// visible counts are reached or `config.max_integrations` have run. The
+    /// dark offset for these settings is subtracted from the sums and the gain
+    /// calibration applied. Returns [`Error::SignalOverflow`] if the sky is too
+    /// bright for these settings. The cached configuration is restored
+    /// afterwards.
+    pub async fn measure_sky_quality(
+        &mut self,
+        delay: &mut Delay,
+        config: &SqmConfig,
+    ) -> Result<SkyQuality, Error<I2cError>> {
+        let (gain, integration_time, enable) = (self.gain, self.integration_time, self.enable);
+        self.gain = Gain::Max;
+        self.integration_time = IntegrationTime::_600MS;
+        let (dark_0, dark_1) = self.dark_offsets.offset(self.gain, self.integration_time);
+        let (mut sum_0, mut sum_1, mut integrations) = (0u32, 0u32, 0u16);
+        let mut result = Ok(());
+        while integrations < config.max_integrations.max(1) {
+            match self.measure_once_inner(delay).await {
+                Ok(measurement) => {
+                    if check_overflow(self.integration_time, measurement.ch_0, measurement.ch_1) {
+                        result = Err(Error::SignalOverflow);
+                        break;
+                    }
+                    sum_0 += measurement.ch_0 as u32;
+                    sum_1 += measurement.ch_1 as u32;
+                    integrations += 1;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            let ch_0 = sum_0.saturating_sub(dark_0 as u32 * integrations as u32);
+            let ch_1 = sum_1.saturating_sub(dark_1 as u32 * integrations as u32);
+            if ch_0.saturating_sub(ch_1) >= config.target_counts {
+                break;
+            }
+        }
+        let ch_0_counts = sum_0.saturating_sub(dark_0 as u32 * integrations as u32);
+        let ch_1_counts = sum_1.saturating_sub(dark_1 as u32 * integrations as u32);
+        let visible = ch_0_counts.saturating_sub(ch_1_counts) as f32
+            * self.gain_calibration.correction(self.gain);
+        let magnitude = config.magnitude(self.integration_time, visible, integrations);
+
+        self.gain = gain;
+        self.integration_time = integration_time;
+        self.enable = enable;
+        self.restore_configuration().await?;
+        result?;
+        Ok(SkyQuality {
+            magnitude,
+            ch_0_counts,
+            ch_1_counts,
+            integrations,
+        })
+    }
+
+    /// Calculate lux with the algorithm selected by
+    /// [`set_lux_algorithm`](Self::set_lux_algorithm).
+    pub fn lux(&self, ch_0: u16, ch_1: u16) -> Result<f32, Error<I2cError>> {
+        self.lux_with(&self.lux_algorithm, ch_0, ch_1)
+    }
+
+    pub fn nano_lux(&self, ch_0: u16, ch_1: u16) -> Result<i64, Error<I2cError>> {
+        self.nano_lux_with(&self.lux_algorithm, ch_0, ch_1)
+    }
+
+    /// Calculate lux with any converter, e.g. one wrapped in
+    /// [`TemperatureCompensated`](crate::TemperatureCompensated). Dark offsets
+    /// and the gain calibration are applied as in [`lux`](Self::lux), so don't
+    /// also wrap the converter in [`DarkCompensated`](crate::DarkCompensated)
+    /// while [`set_dark_offsets`](Self::set_dark_offsets) is in use.
+    pub fn lux_with<C: DynLuxConverter + ?Sized>(
+        &self,
+        converter: &C,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<f32, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        converter
+            .lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    pub fn nano_lux_with<C: DynLuxConverter + ?Sized>(
+        &self,
+        converter: &C,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<i64, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        converter
+            .nano_lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_nano_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    /// Lux from the selected algorithm with an uncertainty estimate and
+    /// quality flags, after dark and gain calibration.
+    pub fn lux_quality(
+        &self,
+        model: &QualityModel,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<LuxQuality, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        model
+            .assess(
+                &self.lux_algorithm,
+                self.integration_time,
+                self.gain,
+                ch_0,
+                ch_1,
+            )
+            .map(|quality| quality.scaled(self.gain_calibration.correction(self.gain)))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    /// Irradiance estimated with `model`, after dark and gain calibration.
+    pub fn irradiance(
+        &self,
+        model: &SpectralModel,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<Irradiance, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        let correction = self.gain_calibration.correction(self.gain);
+        model
+            .irradiance(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|irradiance| Irradiance {
+                visible: irradiance.visible * correction,
+                infrared: irradiance.infrared * correction,
+                total: irradiance.total * correction,
+            })
+            .ok_or(Error::SignalOverflow)
+    }
+
+    pub fn calculate_lux<T: LuxConverter>(
+        &self,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<f32, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        <T as LuxConverter>::calculate_lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+
+    pub fn calculate_nano_lux<T: LuxConverter>(
+        &self,
+        ch_0: u16,
+        ch_1: u16,
+    ) -> Result<i64, Error<I2cError>> {
+        let (ch_0, ch_1) = self.dark_compensate(ch_0, ch_1)?;
+        <T as LuxConverter>::calculate_nano_lux(self.integration_time, self.gain, ch_0, ch_1)
+            .map(|lux| self.gain_calibration.correct_nano_lux(self.gain, lux))
+            .ok_or(Error::SignalOverflow)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, I2cError, Delay, S> Tsl2591<I2C, Delay, S>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+    S: Measure,
+{
     pub async fn get_channel_data(
         &mut self,
         delay: &mut Delay,
     ) -> Result<(u16, u16), Error<I2cError>> {
         delay.delay_ms(120).await;
-        let mut buffer_1 = [0u8; 2];
-        let mut buffer_2 = [0u8; 2];
-        self.i2c
-            .write_read(
-                chip::I2C,
-                &[chip::COMMAND_BIT | chip::CHAN0_LOW],
-                &mut buffer_1,
-            )
-            .await?;
-        self.i2c
-            .write_read(
-                chip::I2C,
-                &[chip::COMMAND_BIT | chip::CHAN1_LOW],
-                &mut buffer_2,
-            )
-            .await?;
-        let channel_0 = ((buffer_1[0] as u16) << 8) | buffer_1[1] as u16;
-        let channel_1 = ((buffer_2[0] as u16) << 8) | buffer_2[1] as u16;
-        Ok((channel_0, channel_1))
+        self.read_channels().await
     }
 
     pub async fn get_luminosity(
@@ -396,22 +2012,42 @@ where
             }
         }
     }
+}
 
-    pub fn calculate_lux<T: LuxConverter>(
-        &self,
-        ch_0: u16,
-        ch_1: u16,
-    ) -> Result<f32, Error<I2cError>> {
-        <T as LuxConverter>::calculate_lux(self.integration_time, self.gain, ch_0, ch_1)
-            .ok_or(Error::SignalOverflow)
+#[cfg(feature = "async")]
+impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay, Off>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+{
+    /// Power the sensor on and enable the ALS.
+    pub async fn enable(mut self) -> Result<Tsl2591<I2C, Delay, On>, Error<I2cError>> {
+        self.power_on().await?;
+        Ok(self.into_state())
     }
 
-    pub fn calculate_nano_lux<T: LuxConverter>(
-        &self,
-        ch_0: u16,
-        ch_1: u16,
-    ) -> Result<i64, Error<I2cError>> {
-        <T as LuxConverter>::calculate_nano_lux(self.integration_time, self.gain, ch_0, ch_1)
-            .ok_or(Error::SignalOverflow)
+    /// Take a single measurement with minimal on-time.
+    ///
+    /// Powers the sensor on with the cached gain and integration time, waits
+    /// for exactly one valid integration cycle, reads both channels and powers
+    /// the sensor off again.
+    pub async fn measure_once(
+        &mut self,
+        delay: &mut Delay,
+    ) -> Result<Measurement, Error<I2cError>> {
+        self.measure_once_inner(delay).await
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, I2cError, Delay> Tsl2591<I2C, Delay, On>
+where
+    I2C: I2c<SevenBitAddress, Error = I2cError>,
+    Delay: DelayNs,
+{
+    /// Power the sensor off.
+    pub async fn disable(mut self) -> Result<Tsl2591<I2C, Delay, Off>, Error<I2cError>> {
+        self.power_off().await?;
+        Ok(self.into_state())
     }
 }
@@ -0,0 +1,109 @@
+//! Sky brightness in magnitudes per square arcsecond.
+
+use crate::types::IntegrationTime;
+
+/// Settings for [`Tsl2591::measure_sky_quality`](crate::Tsl2591::measure_sky_quality).
+///
+/// Sky brightness is computed from the visible counts (CH0 − CH1) per second
+/// at [`Gain::Max`](crate::Gain::Max) as
+///
+/// ```text
+/// mag/arcsec² = zero_point - 2.5 * log10(counts per second)
+/// ```
+///
+/// The zero point depends on the optics in front of the sensor and has to be
+/// calibrated against a reference sky quality meter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SqmConfig {
+    pub zero_point: f32,
+    /// Stop once this many dark-subtracted visible counts have accumulated
+    pub target_counts: u32,
+    /// Upper bound on the number of 600 ms integrations
+    pub max_integrations: u16,
+}
+
+impl SqmConfig {
+    pub fn new(zero_point: f32) -> Self {
+        SqmConfig {
+            zero_point,
+            target_counts: 1000,
+            max_integrations: 32,
+        }
+    }
+
+    /// Sky brightness for `visible_counts` accumulated over `integrations`
+    /// cycles of `integration_time`. `None` without any signal.
+    pub fn magnitude(
+        &self,
+        integration_time: IntegrationTime,
+        visible_counts: f32,
+        integrations: u16,
+    ) -> Option<f32> {
+        if visible_counts <= 0.0 || integrations == 0 {
+            return None;
+        }
+        let seconds =
+            integrations as f32 * integration_time.get_integration_time_millis() as f32 / 1000.0;
+        Some(self.zero_point - 2.5 * libm::log10f(visible_counts / seconds))
+    }
+}
+
+/// Result of a sky quality measurement.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkyQuality {
+    /// mag/arcsec², `None` if no light above the dark offset was detected
+    pub magnitude: Option<f32>,
+    /// Accumulated CH0 counts after dark subtraction
+    pub ch_0_counts: u32,
+    /// Accumulated CH1 counts after dark subtraction
+    pub ch_1_counts: u32,
+    /// Number of integrations accumulated
+    pub integrations: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f32>, expected: f32) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn magnitude_from_counts_per_second() {
+        let config = SqmConfig::new(20.0);
+        // 600 counts in 600 ms is 1000 counts per second
+        assert_close(
+            config.magnitude(IntegrationTime::_600MS, 600.0, 1),
+            20.0 - 7.5,
+        );
+        // Ten times fainter is 2.5 magnitudes more
+        assert_close(
+            config.magnitude(IntegrationTime::_600MS, 60.0, 1),
+            20.0 - 5.0,
+        );
+        // The same counts spread over more integrations are fainter
+        assert_close(
+            config.magnitude(IntegrationTime::_600MS, 600.0, 10),
+            20.0 - 5.0,
+        );
+        assert_close(
+            config.magnitude(IntegrationTime::_100MS, 100.0, 10),
+            20.0 - 5.0,
+        );
+    }
+
+    #[test]
+    fn no_magnitude_without_signal() {
+        let config = SqmConfig::new(20.0);
+        assert_eq!(config.magnitude(IntegrationTime::_600MS, 0.0, 4), None);
+        assert_eq!(config.magnitude(IntegrationTime::_600MS, -3.0, 4), None);
+        assert_eq!(config.magnitude(IntegrationTime::_600MS, 100.0, 0), None);
+    }
+}
@@ -0,0 +1,31 @@
+//! Type-level power states for [`Tsl2591`](crate::Tsl2591).
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Power state of a [`Tsl2591`](crate::Tsl2591).
+pub trait PowerState: sealed::Sealed {}
+
+/// Power states in which measurements can be taken.
+pub trait Measure: PowerState {}
+
+/// The power state is not tracked by the type system. This is the default.
+pub struct Untracked;
+
+/// The sensor is powered off.
+pub struct Off;
+
+/// The sensor is powered on with the ALS enabled.
+pub struct On;
+
+impl sealed::Sealed for Untracked {}
+impl sealed::Sealed for Off {}
+impl sealed::Sealed for On {}
+
+impl PowerState for Untracked {}
+impl PowerState for Off {}
+impl PowerState for On {}
+
+impl Measure for Untracked {}
+impl Measure for On {}
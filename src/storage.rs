@@ -0,0 +1,636 @@
+//! Persist configuration and calibration data to non-volatile storage.
+//!
+//! Each value is stored as a small self-describing record:
+//!
+//! | bytes | content                                   |
+//! |-------|-------------------------------------------|
+//! | 2     | magic, `b"TS"`                            |
+//! | 1     | record kind ([`Persist::KIND`])           |
+//! | 1     | payload version ([`Persist::VERSION`])    |
+//! | 1     | payload length                            |
+//! | n     | payload                                   |
+//! | 2     | CRC-16/CCITT-FALSE of all preceding bytes |
+//!
+//! Records written by older firmware carry an older payload version and are
+//! migrated by [`Persist::decode`] when loaded.
+
+use crate::{
+    calibration::{DarkOffsets, GainCalibration},
+    config::Config,
+    lux_conversion::{AmsLuxConverter, LuxAlgorithm, LuxCoefficients},
+    types::{Enable, Gain, IntegrationTime, Persistence},
+};
+use core::convert::TryFrom;
+use embedded_storage::{nor_flash::NorFlash, ReadStorage};
+
+const MAGIC: [u8; 2] = *b"TS";
+const HEADER_SIZE: usize = 5;
+const CRC_SIZE: usize = 2;
+/// Largest payload a record can hold.
+pub const MAX_PAYLOAD_SIZE: usize = 249;
+/// Largest encoded record, header and checksum included.
+pub const MAX_RECORD_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + CRC_SIZE;
+
+/// A value that can be stored in a record.
+pub trait Persist: Sized {
+    /// Tags the record so that one type is never decoded as another.
+    const KIND: u8;
+    /// Payload version produced by [`encode`](Self::encode).
+    const VERSION: u8;
+
+    /// Write the current-version payload into `buf` (at least
+    /// [`MAX_PAYLOAD_SIZE`] long) and return its length.
+    fn encode(&self, buf: &mut [u8]) -> usize;
+
+    /// Decode a payload written with `version`, which is never newer than
+    /// [`VERSION`](Self::VERSION). Older versions must be migrated.
+    fn decode(version: u8, payload: &[u8]) -> Option<Self>;
+}
+
+/// Why a record could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordError {
+    /// No record magic, e.g. erased or never-written storage
+    Missing,
+    /// The record holds a different kind of value
+    Kind(u8),
+    /// The record was written by newer firmware
+    UnsupportedVersion(u8),
+    /// The record is truncated or its length field is out of range
+    Length,
+    Checksum,
+    /// The checksum matched but the payload did not decode
+    Payload,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageError<E> {
+    Storage(E),
+    Record(RecordError),
+    /// The record cannot be padded to the flash write size
+    Alignment,
+}
+
+impl<E> From<RecordError> for StorageError<E> {
+    fn from(error: RecordError) -> Self {
+        StorageError::Record(error)
+    }
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Encode `value` into `buf` and return the record length.
+pub fn encode_record<T: Persist>(value: &T, buf: &mut [u8; MAX_RECORD_SIZE]) -> usize {
+    let len = value.encode(&mut buf[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD_SIZE]);
+    buf[..2].copy_from_slice(&MAGIC);
+    buf[2] = T::KIND;
+    buf[3] = T::VERSION;
+    buf[4] = len as u8;
+    let end = HEADER_SIZE + len;
+    let crc = crc16(&buf[..end]);
+    buf[end..end + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+    end + CRC_SIZE
+}
+
+/// Validate and decode a record, migrating older payload versions.
+pub fn decode_record<T: Persist>(record: &[u8]) -> Result<T, RecordError> {
+    if record.len() < HEADER_SIZE || record[..2] != MAGIC {
+        return Err(RecordError::Missing);
+    }
+    let len = record[4] as usize;
+    let end = HEADER_SIZE + len;
+    if len > MAX_PAYLOAD_SIZE || record.len() < end + CRC_SIZE {
+        return Err(RecordError::Length);
+    }
+    let crc = u16::from_le_bytes([record[end], record[end + 1]]);
+    if crc != crc16(&record[..end]) {
+        return Err(RecordError::Checksum);
+    }
+    if record[2] != T::KIND {
+        return Err(RecordError::Kind(record[2]));
+    }
+    let version = record[3];
+    if version > T::VERSION {
+        return Err(RecordError::UnsupportedVersion(version));
+    }
+    T::decode(version, &record[HEADER_SIZE..end]).ok_or(RecordError::Payload)
+}
+
+// `usize::div_ceil` and `is_multiple_of` would raise the MSRV.
+#[allow(clippy::manual_div_ceil)]
+fn round_up(len: usize, multiple: usize) -> usize {
+    (len + multiple - 1) / multiple * multiple
+}
+
+/// `value` if it is finite and above zero.
+fn positive(value: f32) -> Option<f32> {
+    if value.is_finite() && value > 0.0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// `value` if it is finite.
+fn finite(value: f32) -> Option<f32> {
+    if value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Erase the sector(s) at `offset` and write `value` there as a record.
+///
+/// `offset` must be aligned to the flash erase size.
+#[allow(clippy::manual_is_multiple_of)]
+pub fn save<F: NorFlash, T: Persist>(
+    flash: &mut F,
+    offset: u32,
+    value: &T,
+) -> Result<(), StorageError<F::Error>> {
+    let mut buf = [0xFFu8; MAX_RECORD_SIZE];
+    let len = encode_record(value, &mut buf);
+    let write_len = round_up(len, F::WRITE_SIZE);
+    if write_len > buf.len() || offset as usize % F::ERASE_SIZE != 0 {
+        return Err(StorageError::Alignment);
+    }
+    let erase_len = round_up(write_len, F::ERASE_SIZE);
+    flash
+        .erase(offset, offset + erase_len as u32)
+        .map_err(StorageError::Storage)?;
+    flash
+        .write(offset, &buf[..write_len])
+        .map_err(StorageError::Storage)
+}
+
+/// Read, validate and decode a record at `offset`.
+pub fn load<S: ReadStorage, T: Persist>(
+    storage: &mut S,
+    offset: u32,
+) -> Result<T, StorageError<S::Error>> {
+    let mut buf = [0u8; MAX_RECORD_SIZE];
+    storage
+        .read(offset, &mut buf[..HEADER_SIZE])
+        .map_err(StorageError::Storage)?;
+    if buf[..2] != MAGIC {
+        return Err(RecordError::Missing.into());
+    }
+    let len = buf[4] as usize;
+    if len > MAX_PAYLOAD_SIZE {
+        return Err(RecordError::Length.into());
+    }
+    let end = HEADER_SIZE + len + CRC_SIZE;
+    storage
+        .read(offset + HEADER_SIZE as u32, &mut buf[HEADER_SIZE..end])
+        .map_err(StorageError::Storage)?;
+    Ok(decode_record(&buf[..end])?)
+}
+
+impl Persist for Config {
+    const KIND: u8 = 0x01;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.gain as u8;
+        buf[1] = self.integration_time as u8;
+        buf[2] = self.enable.0;
+        buf[3] = self.persistence as u8;
+        let thresholds = [
+            self.thresholds.0,
+            self.thresholds.1,
+            self.no_persist_thresholds.0,
+            self.no_persist_thresholds.1,
+        ];
+        for (chunk, threshold) in buf[4..12].chunks_exact_mut(2).zip(thresholds.iter()) {
+            chunk.copy_from_slice(&threshold.to_le_bytes());
+        }
+        12
+    }
+
+    fn decode(_version: u8, payload: &[u8]) -> Option<Self> {
+        if payload.len() < 12 {
+            return None;
+        }
+        let word = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+        let config = Config {
+            gain: Gain::try_from(payload[0]).ok()?,
+            integration_time: IntegrationTime::try_from(payload[1]).ok()?,
+            enable: Enable(payload[2]),
+            persistence: Persistence::try_from(payload[3]).ok()?,
+            thresholds: (word(4), word(6)),
+            no_persist_thresholds: (word(8), word(10)),
+        };
+        // Reject anything the builder would not have produced
+        config.to_builder().build().ok()
+    }
+}
+
+impl Persist for LuxAlgorithm {
+    const KIND: u8 = 0x02;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        match self {
+            LuxAlgorithm::AdafruitPython => buf[0] = 0,
+            LuxAlgorithm::Yocto => buf[0] = 1,
+            LuxAlgorithm::AmsSunlight => buf[0] = 2,
+            LuxAlgorithm::Ams(converter) => {
+                buf[0] = 3;
+                let c = converter.coefficients();
+                let values = [
+                    c.device_factor,
+                    c.glass_attenuation,
+                    c.coef_b,
+                    c.coef_c,
+                    c.coef_d,
+                ];
+                for (chunk, value) in buf[1..21].chunks_exact_mut(4).zip(values.iter()) {
+                    chunk.copy_from_slice(&value.to_le_bytes());
+                }
+                return 21;
+            }
+        }
+        1
+    }
+
+    fn decode(_version: u8, payload: &[u8]) -> Option<Self> {
+        match payload.first()? {
+            0 => Some(LuxAlgorithm::AdafruitPython),
+            1 => Some(LuxAlgorithm::Yocto),
+            2 => Some(LuxAlgorithm::AmsSunlight),
+            3 if payload.len() >= 21 => {
+                let value = |i: usize| {
+                    f32::from_le_bytes([payload[i], payload[i + 1], payload[i + 2], payload[i + 3]])
+                };
+                // The device factor and glass attenuation scale every result
+                Some(LuxAlgorithm::Ams(AmsLuxConverter::new(LuxCoefficients {
+                    device_factor: positive(value(1))?,
+                    glass_attenuation: positive(value(5))?,
+                    coef_b: finite(value(9))?,
+                    coef_c: finite(value(13))?,
+                    coef_d: finite(value(17))?,
+                })))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Persist for GainCalibration {
+    const KIND: u8 = 0x03;
+    const VERSION: u8 = 2;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        // Bit n of the first byte marks step n as measured
+        buf[0] = 0;
+        for (i, step) in self.steps().iter().enumerate() {
+            if step.is_some() {
+                buf[0] |= 1 << i;
+            }
+            let value = step.unwrap_or(0.0);
+            buf[1 + 4 * i..5 + 4 * i].copy_from_slice(&value.to_le_bytes());
+        }
+        13
+    }
+
+    fn decode(version: u8, payload: &[u8]) -> Option<Self> {
+        let value = |i: usize| {
+            f32::from_le_bytes([payload[i], payload[i + 1], payload[i + 2], payload[i + 3]])
+        };
+        match version {
+            // Version 1 stored the Med, High and Max multipliers relative to
+            // Low, with unmeasured steps already filled in as nominal.
+            1 if payload.len() >= 12 => Some(GainCalibration::new(
+                positive(value(0))?,
+                positive(value(4))?,
+                positive(value(8))?,
+            )),
+            2 if payload.len() >= 13 => {
+                let mut calibration = GainCalibration::NOMINAL;
+                for (i, &gain) in Gain::ALL[1..].iter().enumerate() {
+                    if payload[0] & (1 << i) != 0 {
+                        calibration.set_step(gain, Some(positive(value(1 + 4 * i))?));
+                    }
+                }
+                Some(calibration)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Persist for DarkOffsets {
+    const KIND: u8 = 0x04;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        for &gain in &Gain::ALL {
+            for &integration_time in &IntegrationTime::ALL {
+                let (ch_0, ch_1) = self.offset(gain, integration_time);
+                buf[len..len + 2].copy_from_slice(&ch_0.to_le_bytes());
+                buf[len + 2..len + 4].copy_from_slice(&ch_1.to_le_bytes());
+                len += 4;
+            }
+        }
+        len
+    }
+
+    fn decode(_version: u8, payload: &[u8]) -> Option<Self> {
+        if payload.len() < Gain::ALL.len() * IntegrationTime::ALL.len() * 4 {
+            return None;
+        }
+        let word = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+        let mut offsets = DarkOffsets::default();
+        let mut i = 0;
+        for &gain in &Gain::ALL {
+            for &integration_time in &IntegrationTime::ALL {
+                offsets.set_offset(gain, integration_time, (word(i), word(i + 2)));
+                i += 4;
+            }
+        }
+        Some(offsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    struct Flash([u8; 512]);
+
+    impl Flash {
+        fn erased() -> Self {
+            Flash([0xFF; 512])
+        }
+    }
+
+    impl ErrorType for Flash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for Flash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl NorFlash for Flash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 256;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.0[from as usize..to as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            // NOR flash can only clear bits
+            for (cell, &byte) in self.0[offset as usize..].iter_mut().zip(bytes) {
+                *cell &= byte;
+            }
+            Ok(())
+        }
+    }
+
+    impl ReadStorage for Flash {
+        type Error = NorFlashErrorKind;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            ReadNorFlash::read(self, offset, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    fn round_trip<T: Persist + Copy + PartialEq + core::fmt::Debug>(value: T) {
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = encode_record(&value, &mut buf);
+        assert_eq!(decode_record::<T>(&buf[..len]), Ok(value));
+
+        let mut flash = Flash::erased();
+        save(&mut flash, 256, &value).unwrap();
+        assert_eq!(load::<_, T>(&mut flash, 256), Ok(value));
+    }
+
+    fn config() -> Config {
+        Config::builder()
+            .gain(Gain::High)
+            .integration_time(IntegrationTime::_300MS)
+            .als(true)
+            .als_interrupt(true)
+            .thresholds(100, 20_000)
+            .no_persist_thresholds(10, 60_000)
+            .persistence(Persistence::_5)
+            .build()
+            .unwrap()
+    }
+
+    /// A record with an arbitrary header, as older firmware would have written.
+    fn raw_record(kind: u8, version: u8, payload: &[u8], buf: &mut [u8; MAX_RECORD_SIZE]) -> usize {
+        buf[..2].copy_from_slice(&MAGIC);
+        buf[2] = kind;
+        buf[3] = version;
+        buf[4] = payload.len() as u8;
+        let end = HEADER_SIZE + payload.len();
+        buf[HEADER_SIZE..end].copy_from_slice(payload);
+        let crc = crc16(&buf[..end]);
+        buf[end..end + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+        end + CRC_SIZE
+    }
+
+    #[test]
+    fn config_round_trip() {
+        round_trip(config());
+        round_trip(Config::low_light());
+    }
+
+    #[test]
+    fn lux_algorithm_round_trip() {
+        round_trip(LuxAlgorithm::AdafruitPython);
+        round_trip(LuxAlgorithm::Yocto);
+        round_trip(LuxAlgorithm::AmsSunlight);
+        round_trip(LuxAlgorithm::Ams(AmsLuxConverter::new(LuxCoefficients {
+            glass_attenuation: 2.5,
+            ..LuxCoefficients::DN40
+        })));
+    }
+
+    #[test]
+    fn gain_calibration_round_trip() {
+        round_trip(GainCalibration::NOMINAL);
+        round_trip(GainCalibration::new(24.1, 440.0, 10_100.0));
+        let mut partial = GainCalibration::NOMINAL;
+        partial.set_step(Gain::Max, Some(23.5));
+        round_trip(partial);
+    }
+
+    #[test]
+    fn dark_offsets_round_trip() {
+        let mut offsets = DarkOffsets::default();
+        offsets.set_offset(Gain::Max, IntegrationTime::_600MS, (37, 12));
+        offsets.set_offset(Gain::Low, IntegrationTime::_100MS, (1, 0));
+        round_trip(offsets);
+    }
+
+    #[test]
+    fn corrupted_record() {
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = encode_record(&config(), &mut buf);
+        buf[HEADER_SIZE + 2] ^= 0x01;
+        assert_eq!(
+            decode_record::<Config>(&buf[..len]),
+            Err(RecordError::Checksum)
+        );
+    }
+
+    #[test]
+    fn kind_mismatch() {
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = encode_record(&config(), &mut buf);
+        assert_eq!(
+            decode_record::<DarkOffsets>(&buf[..len]),
+            Err(RecordError::Kind(Config::KIND))
+        );
+    }
+
+    #[test]
+    fn truncated_record() {
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = encode_record(&config(), &mut buf);
+        assert_eq!(
+            decode_record::<Config>(&buf[..len - 1]),
+            Err(RecordError::Length)
+        );
+        buf[4] = MAX_PAYLOAD_SIZE as u8 + 1;
+        assert_eq!(decode_record::<Config>(&buf), Err(RecordError::Length));
+    }
+
+    #[test]
+    fn erased_flash() {
+        let mut flash = Flash::erased();
+        assert_eq!(
+            load::<_, Config>(&mut flash, 0),
+            Err(StorageError::Record(RecordError::Missing))
+        );
+        assert_eq!(
+            decode_record::<Config>(&[0xFF; MAX_RECORD_SIZE]),
+            Err(RecordError::Missing)
+        );
+    }
+
+    #[test]
+    fn newer_version_rejected() {
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = raw_record(Config::KIND, Config::VERSION + 1, &[0; 12], &mut buf);
+        assert_eq!(
+            decode_record::<Config>(&buf[..len]),
+            Err(RecordError::UnsupportedVersion(Config::VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn gain_calibration_migrates_version_1() {
+        let mut payload = [0u8; 12];
+        for (chunk, value) in payload
+            .chunks_exact_mut(4)
+            .zip([24.5f32, 420.0, 9_700.0].iter())
+        {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = raw_record(GainCalibration::KIND, 1, &payload, &mut buf);
+
+        let calibration = decode_record::<GainCalibration>(&buf[..len]).unwrap();
+        assert!(calibration.is_complete());
+        assert_eq!(calibration.multiplier(Gain::Med), 24.5);
+        assert!((calibration.multiplier(Gain::High) - 420.0).abs() < 1e-3);
+        assert!((calibration.multiplier(Gain::Max) - 9_700.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn rejects_invalid_coefficients() {
+        let ams = |values: [f32; 5]| {
+            let mut payload = [3u8; 21];
+            for (chunk, value) in payload[1..].chunks_exact_mut(4).zip(values.iter()) {
+                chunk.copy_from_slice(&value.to_le_bytes());
+            }
+            let mut buf = [0u8; MAX_RECORD_SIZE];
+            let len = raw_record(LuxAlgorithm::KIND, 1, &payload, &mut buf);
+            decode_record::<LuxAlgorithm>(&buf[..len])
+        };
+        // Negative equation coefficients are a valid fit
+        assert!(ams([408.0, 1.0, 1.64, -0.1, 0.0]).is_ok());
+        for values in [
+            [0.0, 1.0, 1.64, 0.59, 0.86],
+            [-408.0, 1.0, 1.64, 0.59, 0.86],
+            [408.0, 0.0, 1.64, 0.59, 0.86],
+            [f32::NAN, 1.0, 1.64, 0.59, 0.86],
+            [408.0, f32::INFINITY, 1.64, 0.59, 0.86],
+            [408.0, 1.0, f32::NAN, 0.59, 0.86],
+            [408.0, 1.0, 1.64, 0.59, f32::NEG_INFINITY],
+        ] {
+            assert_eq!(ams(values), Err(RecordError::Payload), "{:?}", values);
+        }
+
+        let gain_v2 = |step: f32| {
+            let mut payload = [0u8; 13];
+            payload[0] = 0b010;
+            payload[5..9].copy_from_slice(&step.to_le_bytes());
+            let mut buf = [0u8; MAX_RECORD_SIZE];
+            let len = raw_record(GainCalibration::KIND, 2, &payload, &mut buf);
+            decode_record::<GainCalibration>(&buf[..len])
+        };
+        assert!(gain_v2(17.0).is_ok());
+        for step in [0.0, -17.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(gain_v2(step), Err(RecordError::Payload), "{}", step);
+        }
+        // Unmeasured steps are not looked at
+        let mut payload = [0xFFu8; 13];
+        payload[0] = 0;
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        let len = raw_record(GainCalibration::KIND, 2, &payload, &mut buf);
+        assert_eq!(
+            decode_record::<GainCalibration>(&buf[..len]),
+            Ok(GainCalibration::NOMINAL)
+        );
+
+        let mut payload = [0u8; 12];
+        for (chunk, value) in payload
+            .chunks_exact_mut(4)
+            .zip([25.0f32, 0.0, 9876.0].iter())
+        {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        let len = raw_record(GainCalibration::KIND, 1, &payload, &mut buf);
+        assert_eq!(
+            decode_record::<GainCalibration>(&buf[..len]),
+            Err(RecordError::Payload)
+        );
+    }
+}
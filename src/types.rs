@@ -1,6 +1,8 @@
 #![allow(non_snake_case)]
 
+use crate::lux_conversion::LuxConverter;
 use bitfield::bitfield;
+use core::convert::TryFrom;
 
 pub enum Mode {
     Infrared,
@@ -19,6 +21,15 @@ pub enum IntegrationTime {
 }
 
 impl IntegrationTime {
+    pub const ALL: [IntegrationTime; 6] = [
+        Self::_100MS,
+        Self::_200MS,
+        Self::_300MS,
+        Self::_400MS,
+        Self::_500MS,
+        Self::_600MS,
+    ];
+
     pub fn get_integration_time_millis(&self) -> u32 {
         match self {
             Self::_100MS => 100,
@@ -29,6 +40,14 @@ impl IntegrationTime {
             Self::_600MS => 600,
         }
     }
+
+    /// Largest count either channel can reach at this integration time.
+    pub fn max_count(&self) -> u16 {
+        match self {
+            Self::_100MS => 36863,
+            _ => 65535,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
@@ -39,7 +58,26 @@ pub enum Gain {
     Max = 0x30,  // max gain (9876x)
 }
 
+impl TryFrom<u8> for IntegrationTime {
+    type Error = u8;
+
+    /// Decode the ATIME field of the CONTROL register.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Self::_100MS),
+            0x01 => Ok(Self::_200MS),
+            0x02 => Ok(Self::_300MS),
+            0x03 => Ok(Self::_400MS),
+            0x04 => Ok(Self::_500MS),
+            0x05 => Ok(Self::_600MS),
+            other => Err(other),
+        }
+    }
+}
+
 impl Gain {
+    pub const ALL: [Gain; 4] = [Self::Low, Self::Med, Self::High, Self::Max];
+
     pub fn get_multiplier(&self) -> u32 {
         match self {
             Self::Low => 1,
@@ -50,22 +88,234 @@ impl Gain {
     }
 }
 
+impl TryFrom<u8> for Gain {
+    type Error = u8;
+
+    /// Decode the AGAIN field of the CONTROL register (bits 5:4, unshifted).
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Self::Low),
+            0x10 => Ok(Self::Med),
+            0x20 => Ok(Self::High),
+            0x30 => Ok(Self::Max),
+            other => Err(other),
+        }
+    }
+}
+
+/// Number of consecutive out-of-range ALS cycles required before the
+/// persistent interrupt (AINT) fires.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Persistence {
+    Every = 0x00, // every ALS cycle
+    Any = 0x01,   // any value outside of the thresholds
+    _2 = 0x02,    // 2 consecutive values out of range
+    _3 = 0x03,
+    _5 = 0x04,
+    _10 = 0x05,
+    _15 = 0x06,
+    _20 = 0x07,
+    _25 = 0x08,
+    _30 = 0x09,
+    _35 = 0x0A,
+    _40 = 0x0B,
+    _45 = 0x0C,
+    _50 = 0x0D,
+    _55 = 0x0E,
+    _60 = 0x0F,
+}
+
+impl TryFrom<u8> for Persistence {
+    type Error = u8;
+
+    /// Decode the APERS field of the PERSIST register.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x00 => Self::Every,
+            0x01 => Self::Any,
+            0x02 => Self::_2,
+            0x03 => Self::_3,
+            0x04 => Self::_5,
+            0x05 => Self::_10,
+            0x06 => Self::_15,
+            0x07 => Self::_20,
+            0x08 => Self::_25,
+            0x09 => Self::_30,
+            0x0A => Self::_35,
+            0x0B => Self::_40,
+            0x0C => Self::_45,
+            0x0D => Self::_50,
+            0x0E => Self::_55,
+            0x0F => Self::_60,
+            other => return Err(other),
+        })
+    }
+}
+
+/// Raw channel data from one integration cycle, together with the settings
+/// it was taken with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    pub ch_0: u16,
+    pub ch_1: u16,
+    pub integration_time: IntegrationTime,
+    pub gain: Gain,
+}
+
+impl Measurement {
+    pub fn lux<T: LuxConverter>(&self) -> Option<f32> {
+        T::calculate_lux(self.integration_time, self.gain, self.ch_0, self.ch_1)
+    }
+
+    pub fn nano_lux<T: LuxConverter>(&self) -> Option<i64> {
+        T::calculate_nano_lux(self.integration_time, self.gain, self.ch_0, self.ch_1)
+    }
+}
+
+/// Lux value that may be limited by sensor saturation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LuxEstimate {
+    Exact(f32),
+    /// The sensor saturated even at its least sensitive setting; the light is
+    /// at least this bright.
+    AtLeast(f32),
+}
+
+impl LuxEstimate {
+    pub fn value(&self) -> f32 {
+        match *self {
+            LuxEstimate::Exact(lux) | LuxEstimate::AtLeast(lux) => lux,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        matches!(self, LuxEstimate::Exact(_))
+    }
+}
+
+/// Identification registers of the connected part.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// ID register (0x12), `0x50` for the TSL2591
+    pub id: u8,
+    /// PID register (0x11), package identification in bits 5:4
+    pub package_id: u8,
+}
+
 bitfield! {
+    /// ENABLE register (0x00)
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
     pub struct Enable(u8);
     impl Debug;
-    pub NPIEN, _: 6;
-    pub SAI, _: 5;
-    pub RES, _: 4,3;
-    pub AEN, _: 1;
-    pub PON, _: 0;
+    pub NPIEN, set_NPIEN: 7;
+    pub SAI, set_SAI: 6;
+    pub AIEN, set_AIEN: 4;
+    pub AEN, set_AEN: 1;
+    pub PON, set_PON: 0;
+}
+
+bitfield! {
+    /// CONTROL register (0x01)
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Control(u8);
+    impl Debug;
+    pub SRESET, set_SRESET: 7;
+    pub u8, AGAIN, set_AGAIN: 5, 4;
+    pub u8, ATIME, set_ATIME: 2, 0;
+}
+
+impl Control {
+    pub fn new(integration_time: IntegrationTime, gain: Gain) -> Self {
+        let mut control = Control(0);
+        control.set_gain(gain);
+        control.set_integration_time(integration_time);
+        control
+    }
+
+    pub fn gain(&self) -> Gain {
+        // AGAIN is two bits wide, so every value maps to a gain
+        Gain::try_from(self.AGAIN() << 4).unwrap_or(Gain::Low)
+    }
+
+    pub fn set_gain(&mut self, gain: Gain) {
+        self.set_AGAIN(gain as u8 >> 4);
+    }
+
+    /// `None` if ATIME holds one of the reserved values.
+    pub fn integration_time(&self) -> Option<IntegrationTime> {
+        IntegrationTime::try_from(self.ATIME()).ok()
+    }
+
+    pub fn set_integration_time(&mut self, integration_time: IntegrationTime) {
+        self.set_ATIME(integration_time as u8);
+    }
 }
 
 bitfield! {
-    #[allow(non_snake_case)]
+    /// STATUS register (0x13)
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
     pub struct Status(u8);
     impl Debug;
-    pub NPINTR,_: 6,4;
-    pub AINT, _: 4;
-    pub RES, _: 3,3;
-    pub AVALID, _: 0;
+    pub NPINTR, set_NPINTR: 5;
+    pub AINT, set_AINT: 4;
+    pub AVALID, set_AVALID: 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enable_round_trip() {
+        let mut enable = Enable::default();
+        enable.set_PON(true);
+        enable.set_AEN(true);
+        enable.set_AIEN(true);
+        enable.set_NPIEN(true);
+        assert_eq!(enable.0, 0x93);
+
+        let enable = Enable(0x93);
+        assert!(enable.PON() && enable.AEN() && enable.AIEN() && enable.NPIEN());
+        assert!(!enable.SAI());
+
+        let mut enable = Enable(0xFF);
+        enable.set_SAI(false);
+        assert_eq!(enable.0, 0xBF);
+    }
+
+    #[test]
+    fn control_round_trip() {
+        for &gain in &[Gain::Low, Gain::Med, Gain::High, Gain::Max] {
+            for &time in &[
+                IntegrationTime::_100MS,
+                IntegrationTime::_200MS,
+                IntegrationTime::_300MS,
+                IntegrationTime::_400MS,
+                IntegrationTime::_500MS,
+                IntegrationTime::_600MS,
+            ] {
+                let control = Control::new(time, gain);
+                assert_eq!(control.0, time as u8 | gain as u8);
+                assert_eq!(Control(control.0).gain(), gain);
+                assert_eq!(Control(control.0).integration_time(), Some(time));
+            }
+        }
+
+        let mut control = Control::new(IntegrationTime::_100MS, Gain::Max);
+        control.set_SRESET(true);
+        assert_eq!(control.0, 0xB0);
+        assert_eq!(Control(0x07).integration_time(), None);
+    }
+
+    #[test]
+    fn status_round_trip() {
+        let status = Status(0x31);
+        assert!(status.NPINTR() && status.AINT() && status.AVALID());
+        assert!(!Status(0x10).NPINTR());
+
+        let mut status = Status::default();
+        status.set_NPINTR(true);
+        status.set_AVALID(true);
+        assert_eq!(status.0, 0x21);
+    }
 }